@@ -1,19 +1,54 @@
-use crate::request::Request;
+use crate::cookie_jar::{CookieKeys, Key};
+use crate::middleware::{Middleware, Next};
+use crate::range::Ranges;
+use crate::request::{BodyStream, Request};
 use crate::response::Response;
-use crate::router::Router;
-use crate::router::Routes;
+use crate::router::{Handler, Router, Routes};
+use crate::upgrade::Upgraded;
 use crate::Status;
 
-use tokio::io::AsyncWriteExt;
+use std::sync::Arc;
+use tokio::io::{
+    self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::oneshot;
 
 pub struct App {
     pub(crate) router: Router,
+    middleware: Vec<Arc<dyn Middleware>>,
+    cookie_keys: CookieKeys,
 }
 
 impl App {
     pub fn new(router: Router) -> Self {
-        Self { router }
+        Self {
+            router,
+            middleware: Vec::new(),
+            cookie_keys: CookieKeys::default(),
+        }
+    }
+
+    // Middleware runs in the order it was added:
+    pub fn with_middleware(
+        &mut self,
+        middleware: impl Middleware,
+    ) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    // The key used to sign and encrypt cookies in a `CookieJar`:
+    pub fn with_cookie_key(&mut self, key: Key) -> &mut Self {
+        self.cookie_keys.current = Some(key);
+        self
+    }
+
+    // A previous cookie key. Cookies signed or encrypted with it are still
+    // accepted, new cookies use the key from `with_cookie_key`:
+    pub fn with_old_cookie_key(&mut self, key: Key) -> &mut Self {
+        self.cookie_keys.old.push(key);
+        self
     }
 
     pub async fn serve<T: ToSocketAddrs>(
@@ -23,69 +58,652 @@ impl App {
         self.router.build().await;
 
         let listener = TcpListener::bind(addr).await?;
+        let next = self.chain();
 
         loop {
             let (socket, _) = listener.accept().await?;
-            tokio::task::spawn(Self::handle_connection(
-                socket,
-                self.router.routes.clone(),
-            ));
+            tokio::task::spawn(Self::handle_connection(socket, next.clone()));
+        }
+    }
+
+    // The middleware stack, ending in the router:
+    fn chain(&self) -> Next {
+        let routes = self.router.routes.clone();
+        let endpoint: Handler = Arc::new(move |request| {
+            Box::pin(Self::route(routes.clone(), request))
+        });
+
+        let mut middleware = self.middleware.clone();
+        middleware.insert(0, Arc::new(Ranges));
+
+        // Cookie keys are handed to the request before anything else runs:
+        if !self.cookie_keys.is_empty() {
+            let keys = Arc::new(self.cookie_keys.clone());
+            let with_keys = move |mut request: Request, next: Next| {
+                request.extensions_mut().insert(keys.clone());
+                next.run(request)
+            };
+            middleware.insert(0, Arc::new(with_keys));
         }
+
+        Next::new(middleware.into(), endpoint)
+    }
+
+    async fn route(routes: Routes, mut request: Request) -> Response {
+        let found = routes.read().await.find(&request);
+        let handle = match found {
+            Some((handle, mount_path)) => {
+                if let Some(mount_path) = mount_path {
+                    request.extensions_mut().insert(mount_path);
+                }
+                handle
+            }
+            None => return Response::unmatched(),
+        };
+
+        handle(request).await
     }
 
     async fn respond(
-        stream: &mut TcpStream,
+        stream: &mut (impl AsyncWrite + Unpin),
         response: &mut Response,
     ) -> std::io::Result<()> {
-        stream.write_all(response.to_string().as_bytes()).await?;
-        stream.flush().await?;
-        Ok(())
+        // A header that would break the response's framing means the
+        // handler has a bug, so the client gets a plain error instead:
+        if response.validate().is_err() {
+            *response = Response::internal_error();
+        }
+
+        response.write(stream).await
     }
 
     async fn handle_connection(
-        mut stream: TcpStream,
-        routes: Routes,
+        stream: TcpStream,
+        next: Next,
     ) -> tokio::io::Result<()> {
         let mut res = Response::new();
 
-        let req = match Request::from_connection(&mut stream).await {
-            Ok(r) => r,
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        let parsed = match Request::from_head(&mut reader).await {
+            Ok(r) => r.content_length().map(|length| (r, length)),
+            Err(e) => Err(e),
+        };
+
+        let (mut req, length) = match parsed {
+            Ok(parsed) => parsed,
             Err(e) => {
                 dbg!(e);
                 res.with_status(Status::BadRequest)
                     .with_content("Bad request".to_owned());
-                return Self::respond(&mut stream, &mut res).await;
+                return Self::respond(&mut writer, &mut res).await;
             }
         };
 
-        let routes = routes.read().await;
-        let handle = match routes.get(req.route_key()) {
-            Some(handle) => handle,
-            None => {
-                res.with_status(Status::NotFound)
-                    .with_content("Not found".to_owned());
-                return Self::respond(&mut stream, &mut res).await;
+        let expect = req.header("Expect").map(|e| e.to_ascii_lowercase());
+        if expect.is_some() && expect.as_deref() != Some("100-continue") {
+            res.with_status(Status::ExpectationFailed)
+                .with_content("Expectation failed".to_owned());
+            return Self::respond(&mut writer, &mut res).await;
+        }
+
+        // The body is left on the socket. The handler decides whether it
+        // is read into memory or streamed. A client that asked to wait for
+        // `100 Continue` is only told to send it once it is first read:
+        let (continue_tx, mut continue_rx) = oneshot::channel();
+        let mut idle_reader = None;
+        if length == 0 {
+            idle_reader = Some(reader);
+        } else {
+            let mut body = BodyStream::new(reader.take(length as u64));
+            if expect.is_some() {
+                body.on_first_read(continue_tx);
+            }
+
+            req.with_body_stream(body);
+        }
+
+        let mut handling = next.run(req);
+
+        let mut res = tokio::select! {
+            res = &mut handling => res,
+            Ok(()) = &mut continue_rx => {
+                let status = Status::Continue;
+                let interim = format!("HTTP/1.1 {}\r\n\r\n", status);
+                writer.write_all(interim.as_bytes()).await?;
+                writer.flush().await?;
+
+                handling.await
             }
         };
 
-        let mut res = handle(req);
-        Self::respond(&mut stream, &mut res).await?;
+        // The rest of a request body can't be told apart from the new
+        // protocol, so a request with one can't be upgraded:
+        if res.is_upgrade() && idle_reader.is_none() {
+            res = Response::internal_error();
+        }
+
+        // A stream of unknown length, like server-sent events, can go on
+        // indefinitely, so it is dropped once the client hangs up. Bodies
+        // with a length are sent in full, even to a client that has shut
+        // down its side. A hang-up is only noticed when the request had no
+        // body, as reading a body is left to the handler:
+        match &mut idle_reader {
+            Some(reader) if res.body_length().is_none() => tokio::select! {
+                result = Self::respond(&mut writer, &mut res) => result?,
+                _ = Self::hang_up(reader) => {}
+            },
+            _ => Self::respond(&mut writer, &mut res).await?,
+        }
+
+        // After a `101 Switching Protocols` the connection belongs to the
+        // handler's callback. Bytes read past the request head go with it:
+        if let (Some(on_upgrade), Some(reader)) =
+            (res.take_upgrade(), idle_reader)
+        {
+            let buffered = reader.buffer().to_vec();
+            let stream = reader
+                .into_inner()
+                .reunite(writer)
+                .map_err(|e| io::Error::other(e.to_string()))?;
+
+            on_upgrade.run(Upgraded::new(stream, buffered)).await;
+        }
 
         Ok(())
     }
 
+    // Resolves when the client closes the connection. Anything it sends
+    // in the meantime is ignored:
+    async fn hang_up(mut reader: impl AsyncRead + Unpin) {
+        let mut buf = [0; 1024];
+        while let Ok(1..) = reader.read(&mut buf).await {}
+    }
+
     pub async fn request(
         &mut self,
         request: Request,
     ) -> Result<Response, Status> {
         self.router.build().await;
-        let routes = self.router.get_routes().await;
-        let route_key = request.route_key();
-        let handler = match routes.get(route_key) {
-            Some(h) => h,
-            None => Err(Status::NotFound)?,
+
+        // Middleware runs for unmatched paths too, as it does for a
+        // connection:
+        let response = self.chain().run(request).await;
+        match response.is_unmatched() {
+            true => Err(Status::NotFound),
+            false => Ok(response),
+        }
+    }
+}
+
+// Sends a request with the given headers through the app, for the tests
+// of every module:
+#[cfg(test)]
+pub(crate) async fn send_request(
+    app: &mut App,
+    method: crate::Method,
+    target: &str,
+    headers: &[(&str, &str)],
+) -> Result<Response, Status> {
+    let mut request = Request::default();
+    request.with_start_line(method, target, "HTTP/1.1");
+    for (name, value) in headers {
+        request.with_header(name, value);
+    }
+
+    app.request(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::App;
+    use crate::middleware::{BodyLimit, Next};
+    use crate::router::BoxFuture;
+    use crate::sse::{Event, Sse};
+    use crate::websocket::{Message, WebSocketUpgrade};
+    use crate::{Method, Request, Response, Router, Status};
+
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::oneshot;
+
+    async fn connect(app: &mut App) -> TcpStream {
+        app.router.build().await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        tokio::spawn(App::handle_connection(socket, app.chain()));
+
+        client
+    }
+
+    // Sends a raw request, returning everything the server wrote:
+    async fn send(app: &mut App, raw: &[u8]) -> String {
+        let mut client = connect(app).await;
+        client.write_all(raw).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    fn echo(request: Request) -> Response {
+        let mut response = Response::new();
+        response.with_content(request.content().clone().unwrap_or_default());
+        response
+    }
+
+    async fn count(mut request: Request) -> Response {
+        let mut body = request.take_body_stream().unwrap();
+
+        let mut total = 0;
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            total += chunk.len();
+        }
+
+        let mut response = Response::new();
+        response.with_content(total.to_string());
+        response
+    }
+
+    #[tokio::test]
+    async fn test_buffered_body() {
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+        let mut app = App::new(router);
+
+        let response = send(
+            &mut app,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nHello",
+        )
+        .await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHello"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_body() {
+        let mut router = Router::new();
+        router.add_streaming(Method::POST, "/upload", count);
+        let mut app = App::new(router);
+
+        let mut raw =
+            b"POST /upload HTTP/1.1\r\nContent-Length: 100000\r\n\r\n".to_vec();
+        raw.extend(vec![b'x'; 100_000]);
+
+        let response = send(&mut app, &raw).await;
+
+        assert!(response.ends_with("\r\n\r\n100000"));
+    }
+
+    #[tokio::test]
+    async fn test_expect_continue() {
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+        let mut app = App::new(router);
+
+        let mut client = BufReader::new(connect(&mut app).await);
+        client
+            .write_all(
+                b"POST /echo HTTP/1.1\r\nExpect: 100-continue\r\n\
+                  Content-Length: 5\r\n\r\n",
+            )
+            .await
+            .unwrap();
+
+        // Nothing is sent until the server asks for it:
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "HTTP/1.1 100 Continue\r\n");
+        client.read_line(&mut line).await.unwrap();
+
+        client.write_all(b"Hello").await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("Hello"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_before_continue() {
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+        let mut app = App::new(router);
+        app.with_middleware(BodyLimit::new(1024));
+
+        let response = send(
+            &mut app,
+            b"POST /echo HTTP/1.1\r\nExpect: 100-continue\r\n\
+              Content-Length: 4096\r\n\r\n",
+        )
+        .await;
+
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_default_body_limit() {
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+        let mut app = App::new(router);
+
+        // Nothing is allocated for a length the client never sends:
+        let response = send(
+            &mut app,
+            b"POST /echo HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n",
+        )
+        .await;
+
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_request_runs_middleware() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+        let mut app = App::new(router);
+        app.with_middleware(move |request: Request, next: Next| {
+            log.lock().unwrap().push(request.path().clone());
+            next.run(request)
+        });
+
+        let mut request = Request::default();
+        request.with_start_line(Method::GET, "/missing", "HTTP/1.1");
+        let status = app.request(request).await.unwrap_err();
+
+        assert_eq!(status, Status::NotFound);
+        assert_eq!(*seen.lock().unwrap(), ["/missing"]);
+
+        // A 404 made by middleware is a response like any other:
+        let mut app = App::new(Router::new());
+        app.with_middleware(|_: Request, _: Next| -> BoxFuture<Response> {
+            Box::pin(async {
+                let mut response = Response::new();
+                response.with_status(Status::NotFound);
+                response
+            })
+        });
+
+        let response = app.request(Request::default()).await.unwrap();
+        assert_eq!(response.status(), &Status::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_expectation() {
+        let mut app = App::new(Router::new());
+
+        let response =
+            send(&mut app, b"POST /echo HTTP/1.1\r\nExpect: magic\r\n\r\n")
+                .await;
+
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+    }
+
+    fn inject(_: Request) -> Response {
+        let mut response = Response::new();
+        response.with_header("X-Name", "a\r\nContent-Length: 0");
+        response
+    }
+
+    #[tokio::test]
+    async fn test_invalid_header() {
+        let mut router = Router::new();
+        router.add(Method::GET, "/inject", inject);
+        let mut app = App::new(router);
+
+        let response = send(&mut app, b"GET /inject HTTP/1.1\r\n\r\n").await;
+
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(response.contains("\r\nDate: "));
+        assert!(!response.contains("X-Name"));
+    }
+
+    #[tokio::test]
+    async fn test_sse_disconnect() {
+        let (done_tx, done_rx) = oneshot::channel();
+        let done_tx = Arc::new(Mutex::new(Some(done_tx)));
+
+        let mut router = Router::new();
+        router.add_async(Method::GET, "/prices", move |_| {
+            let done_tx = done_tx.lock().unwrap().take().unwrap();
+
+            async move {
+                let (sender, mut sse) = Sse::channel(4);
+                sse.with_keep_alive(Some(Duration::from_millis(20)));
+
+                tokio::spawn(async move {
+                    let mut event = Event::new();
+                    event.with_data("101.5");
+                    sender.send(event).await.unwrap();
+
+                    sender.closed().await;
+                    done_tx.send(()).unwrap();
+                });
+
+                sse.into()
+            }
+        });
+        let mut app = App::new(router);
+
+        let mut client = BufReader::new(connect(&mut app).await);
+        client
+            .write_all(b"GET /prices HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            client.read_line(&mut head).await.unwrap();
+        }
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+
+        // The event, then a keep-alive comment:
+        let mut frames = String::new();
+        while !frames.contains(":\n\n") {
+            client.read_line(&mut frames).await.unwrap();
+        }
+        assert!(frames.contains("data: 101.5\n\n"));
+
+        // The sender notices once the client goes away:
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), done_rx)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    fn download(_: Request) -> Response {
+        let mut response = Response::new();
+        response.with_body_bytes(vec![b'x'; 4_000_000]);
+        response
+    }
+
+    #[tokio::test]
+    async fn test_half_closed_client() {
+        let mut router = Router::new();
+        router.add(Method::GET, "/download", download);
+        let mut app = App::new(router);
+
+        // The client is done sending, but still reads the response:
+        let mut client = connect(&mut app).await;
+        client
+            .write_all(b"GET /download HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).await.unwrap();
+
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&[b'x'; 1000]));
+        let head = response.windows(4).position(|w| w == b"\r\n\r\n");
+        assert_eq!(response.len() - head.unwrap() - 4, 4_000_000);
+    }
+
+    async fn echo_socket(request: Request) -> Response {
+        let upgrade = match request.extract::<WebSocketUpgrade>() {
+            Ok(upgrade) => upgrade,
+            Err(rejection) => return rejection.into(),
         };
 
-        Ok(handler(request))
+        upgrade.on_upgrade(|mut socket| async move {
+            while let Some(Ok(message)) = socket.recv().await {
+                if let Message::Text(text) = message {
+                    socket.send(Message::Text(text)).await.unwrap();
+                }
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_websocket() {
+        let mut router = Router::new();
+        router.add_async(Method::GET, "/ws", echo_socket);
+        let mut app = App::new(router);
+
+        // The first frame is sent along with the handshake, so it is read
+        // into the request buffer before the upgrade:
+        let mut client = BufReader::new(connect(&mut app).await);
+        client
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n\
+                  \x81\x82\x00\x00\x00\x00hi",
+            )
+            .await
+            .unwrap();
+
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            client.read_line(&mut head).await.unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(
+            head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        let mut frame = [0; 4];
+        client.read_exact(&mut frame).await.unwrap();
+        assert_eq!(&frame, b"\x81\x02hi");
+
+        // Ping, then close:
+        client
+            .write_all(b"\x89\x80\x00\x00\x00\x00\x88\x80\x00\x00\x00\x00")
+            .await
+            .unwrap();
+
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"\x8a\x00\x88\x00");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_protocol_error() {
+        let mut router = Router::new();
+        router.add_async(Method::GET, "/ws", echo_socket);
+        let mut app = App::new(router);
+
+        // A close frame with 1005, which is never sent on the wire:
+        let mut client = BufReader::new(connect(&mut app).await);
+        client
+            .write_all(
+                b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n\
+                  \x88\x82\x00\x00\x00\x00\x03\xed",
+            )
+            .await
+            .unwrap();
+
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            client.read_line(&mut head).await.unwrap();
+        }
+
+        // The server fails the connection with 1002:
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"\x88\x02\x03\xea");
+    }
+
+    // Replies to each line with the line upper-cased:
+    fn shout(_: Request) -> Response {
+        let mut response = Response::new();
+        response
+            .with_status(Status::SwitchingProtocols)
+            .with_header("Upgrade", "shout")
+            .with_header("Connection", "Upgrade")
+            .with_upgrade(|upgraded| async move {
+                let (reader, mut writer) = tokio::io::split(upgraded);
+                let mut lines = BufReader::new(reader).lines();
+
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply = format!("{}\n", line.to_uppercase());
+                    writer.write_all(reply.as_bytes()).await.unwrap();
+                }
+            });
+
+        response
+    }
+
+    #[tokio::test]
+    async fn test_upgrade() {
+        let mut router = Router::new();
+        router.add(Method::GET, "/shout", shout).add(
+            Method::POST,
+            "/shout",
+            shout,
+        );
+        let mut app = App::new(router);
+
+        let mut client = BufReader::new(connect(&mut app).await);
+        client
+            .write_all(
+                b"GET /shout HTTP/1.1\r\nUpgrade: shout\r\n\
+                  Connection: Upgrade\r\n\r\nearly\n",
+            )
+            .await
+            .unwrap();
+
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            client.read_line(&mut head).await.unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(!head.contains("Content-Length"));
+
+        // Bytes sent along with the request head aren't lost:
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "EARLY\n");
+
+        client.write_all(b"later\n").await.unwrap();
+        line.clear();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "LATER\n");
+
+        // A request with a body can't be upgraded:
+        let response = send(
+            &mut app,
+            b"POST /shout HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"));
     }
 }
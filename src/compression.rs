@@ -0,0 +1,792 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::{accept, BodyStream, BodyTooLarge, Request};
+use crate::response::body::StreamBody;
+use crate::response::{Response, Status};
+use crate::router::BoxFuture;
+
+use brotli::{CompressorWriter, DecompressorWriter};
+use flate2::write::{GzDecoder, GzEncoder, ZlibDecoder, ZlibEncoder};
+use futures_core::Stream;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+// Brotli's best qualities are too slow to run on every response:
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+// A content coding, in the order we prefer them:
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] =
+        [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "br" => Some(Encoding::Brotli),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+}
+
+// Compresses response bodies in the encoding the client likes best. Only
+// bodies with a compressible Content-Type are, and buffered ones only
+// above a minimum size. Streamed bodies are compressed as they are sent:
+#[derive(Debug)]
+pub struct Compression {
+    min_size: u64,
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Self { min_size: 1024 }
+    }
+
+    // Smaller bodies aren't worth the overhead:
+    pub fn with_min_size(&mut self, min_size: u64) -> &mut Self {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        let encoding =
+            request.header("Accept-Encoding").and_then(|a| negotiate(a));
+        let has_range = request.header("Range").is_some();
+        let min_size = self.min_size;
+
+        Box::pin(async move {
+            let mut response = next.run(request).await;
+
+            let compressible = response.has_body()
+                && !response.is_upgrade()
+                && response.header("Content-Encoding").is_none()
+                && response
+                    .header("Content-Type")
+                    .is_some_and(|t| is_compressible(t));
+            if !compressible {
+                return response;
+            }
+
+            // Whether the body is compressed depends on the request:
+            add_vary(&mut response);
+
+            let encoding = match encoding {
+                Some(encoding) => encoding,
+                None => return response,
+            };
+
+            if response
+                .body_length()
+                .is_some_and(|length| length < min_size)
+            {
+                return response;
+            }
+
+            // The client wants a part of the body as it is:
+            if has_range && response.seekable_length().is_some() {
+                return response;
+            }
+
+            match response.take_body_stream() {
+                Some(body) => {
+                    let trailers = body.trailers.clone();
+                    let encoder = Encoder::new(encoding);
+                    response.with_body_stream(Encode {
+                        body,
+                        encoder: Some(encoder),
+                    });
+
+                    if let Some(trailers) = trailers {
+                        response.with_trailers(&trailers);
+                    }
+                }
+                None => {
+                    let mut encoder = Encoder::new(encoding);
+                    let compressed = encoder
+                        .writer()
+                        .write_all(response.content().as_bytes())
+                        .and_then(|_| encoder.finish());
+
+                    match compressed {
+                        Ok(compressed) => response.with_body_bytes(compressed),
+                        Err(_) => return response,
+                    };
+                }
+            }
+
+            // The compressed body isn't the same bytes any more:
+            if let Some(etag) = response.header("ETag").cloned() {
+                if etag.starts_with('"') {
+                    response.with_header("ETag", &format!("W/{}", etag));
+                }
+            }
+
+            response.with_header("Content-Encoding", encoding.as_str());
+            response
+        })
+    }
+}
+
+// Decompresses request bodies sent with a Content-Encoding, before the
+// route reads them. Bodies in encodings we don't support are rejected
+// with `415 Unsupported Media Type`. A body that inflates past the
+// maximum size fails to read, which routes answer with 413:
+#[derive(Debug)]
+pub struct Decompression {
+    max_size: u64,
+}
+
+impl Decompression {
+    pub fn new() -> Self {
+        Self {
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+
+    // The most bytes a body may decompress to:
+    pub fn with_max_size(&mut self, max_size: u64) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Decompression {
+    fn handle(&self, mut request: Request, next: Next) -> BoxFuture<Response> {
+        let coding = match request.header("Content-Encoding") {
+            Some(coding) => coding.trim().to_owned(),
+            None => return next.run(request),
+        };
+
+        let encoding = match Encoding::from_name(&coding) {
+            Some(encoding) => encoding,
+            None if coding.eq_ignore_ascii_case("identity") => {
+                request.remove_header("Content-Encoding");
+                return next.run(request);
+            }
+            None => return Box::pin(async { unsupported_encoding() }),
+        };
+
+        // A body that was already read is decoded the same way:
+        let body = match request.take_body_stream() {
+            Some(body) => body,
+            None => BodyStream::new(io::Cursor::new(request.body().to_vec())),
+        };
+
+        let decode = Decode {
+            body,
+            decoder: Some(Decoder::new(encoding, self.max_size)),
+            output: Vec::new(),
+            position: 0,
+        };
+
+        // The length was of the compressed body:
+        request
+            .remove_header("Content-Encoding")
+            .remove_header("Content-Length")
+            .with_body_stream(BodyStream::new(decode));
+
+        next.run(request)
+    }
+}
+
+fn unsupported_encoding() -> Response {
+    let supported: Vec<&str> =
+        Encoding::ALL.iter().map(|e| e.as_str()).collect();
+
+    let mut response = Response::new();
+    response
+        .with_status(Status::UnsupportedMediaType)
+        .with_header("Accept-Encoding", &supported.join(", "))
+        .with_content("Unsupported content encoding".to_owned());
+    response
+}
+
+// The encoding to send, from an Accept-Encoding header. The highest
+// q-value wins, ties go to the encoding we prefer. `None` means the body
+// is sent as it is:
+pub(crate) fn negotiate(accept: &str) -> Option<Encoding> {
+    let mut wildcard = None;
+    let mut identity = None;
+    let mut listed = Vec::new();
+
+    for preference in accept::parse(accept) {
+        let (name, q) = (preference.value().as_str(), preference.q());
+
+        match name {
+            "*" => wildcard = wildcard.or(Some(q)),
+            name if name.eq_ignore_ascii_case("identity") => {
+                identity = identity.or(Some(q))
+            }
+            name => {
+                if let Some(encoding) = Encoding::from_name(name) {
+                    listed.push((encoding, q));
+                }
+            }
+        }
+    }
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL {
+        let q = listed
+            .iter()
+            .find(|(listed, _)| *listed == encoding)
+            .map(|(_, q)| *q)
+            .or(wildcard)
+            .unwrap_or(0.0);
+
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+
+    match (best, identity) {
+        (Some((_, q)), Some(identity)) if identity > q => None,
+        (best, _) => best.map(|(encoding, _)| encoding),
+    }
+}
+
+// Text compresses well, images and archives usually already are.
+// Event streams are left alone so each event arrives as it is sent:
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    match essence.split_once('/') {
+        Some(("text", "event-stream")) => false,
+        Some(("text", _)) => true,
+        Some((_, sub)) if sub.ends_with("+json") || sub.ends_with("+xml") => {
+            true
+        }
+        Some(("application", sub)) => {
+            matches!(sub, "json" | "javascript" | "xml" | "wasm")
+        }
+        _ => false,
+    }
+}
+
+fn add_vary(response: &mut Response) {
+    let vary = match response.header("Vary") {
+        Some(vary)
+            if vary.split(',').map(str::trim).any(|field| {
+                field == "*" || field.eq_ignore_ascii_case("Accept-Encoding")
+            }) =>
+        {
+            return
+        }
+        Some(vary) => format!("{}, Accept-Encoding", vary),
+        None => "Accept-Encoding".to_owned(),
+    };
+
+    response.with_header("Vary", &vary);
+}
+
+enum Encoder {
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        let level = flate2::Compression::default();
+
+        match encoding {
+            Encoding::Brotli => {
+                Encoder::Brotli(Box::new(CompressorWriter::new(
+                    Vec::new(),
+                    4096,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                )))
+            }
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), level)),
+            // HTTP's deflate is the zlib format, not raw deflate:
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), level))
+            }
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Brotli(encoder) => encoder.as_mut(),
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Deflate(encoder) => encoder,
+        }
+    }
+
+    // The compressed bytes so far:
+    fn output(&mut self) -> Vec<u8> {
+        let output = match self {
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+        };
+        std::mem::take(output)
+    }
+
+    // Compresses a chunk and flushes it, so the client gets it now
+    // rather than when the compressor's buffer fills up:
+    fn encode(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.writer().write_all(chunk)?;
+        self.writer().flush()?;
+        Ok(self.output())
+    }
+
+    // The rest of the output, once all the input has been written:
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+// Collects decompressed output, failing once there is more than the
+// limit allows:
+struct Limited {
+    output: Vec<u8>,
+    remaining: u64,
+}
+
+impl Write for Limited {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::other(BodyTooLarge));
+        }
+
+        self.remaining -= buf.len() as u64;
+        self.output.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Decoder {
+    Brotli(Box<DecompressorWriter<Limited>>),
+    Gzip(GzDecoder<Limited>),
+    Deflate(ZlibDecoder<Limited>),
+}
+
+impl Decoder {
+    fn new(encoding: Encoding, max_size: u64) -> Self {
+        let limited = Limited {
+            output: Vec::new(),
+            remaining: max_size,
+        };
+
+        match encoding {
+            Encoding::Brotli => Decoder::Brotli(Box::new(
+                DecompressorWriter::new(limited, 4096),
+            )),
+            Encoding::Gzip => Decoder::Gzip(GzDecoder::new(limited)),
+            Encoding::Deflate => Decoder::Deflate(ZlibDecoder::new(limited)),
+        }
+    }
+
+    fn limited(&mut self) -> &mut Limited {
+        match self {
+            Decoder::Brotli(decoder) => decoder.get_mut(),
+            Decoder::Gzip(decoder) => decoder.get_mut(),
+            Decoder::Deflate(decoder) => decoder.get_mut(),
+        }
+    }
+
+    fn decode(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Decoder::Brotli(decoder) => decoder.write_all(chunk)?,
+            Decoder::Gzip(decoder) => decoder.write_all(chunk)?,
+            Decoder::Deflate(decoder) => decoder.write_all(chunk)?,
+        }
+        Ok(std::mem::take(&mut self.limited().output))
+    }
+
+    // The rest of the output. A body that ends early is an error:
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let limited = match self {
+            Decoder::Brotli(mut decoder) => {
+                decoder.close()?;
+                decoder.into_inner().map_err(|_| truncated())?
+            }
+            Decoder::Gzip(decoder) => decoder.finish()?,
+            Decoder::Deflate(decoder) => decoder.finish()?,
+        };
+        Ok(limited.output)
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "truncated compressed body")
+}
+
+// A request body, decompressed as it is read:
+struct Decode {
+    body: BodyStream,
+    decoder: Option<Decoder>,
+    output: Vec<u8>,
+    position: usize,
+}
+
+impl AsyncRead for Decode {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        while this.position == this.output.len() {
+            let decoder = match &mut this.decoder {
+                Some(decoder) => decoder,
+                None => return Poll::Ready(Ok(())),
+            };
+
+            this.output = match ready!(Pin::new(&mut this.body).poll_next(cx)) {
+                Some(Ok(chunk)) => decoder.decode(&chunk)?,
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => match this.decoder.take() {
+                    Some(decoder) => decoder.finish()?,
+                    None => Vec::new(),
+                },
+            };
+            this.position = 0;
+        }
+
+        let n = (this.output.len() - this.position).min(buf.remaining());
+        buf.put_slice(&this.output[this.position..this.position + n]);
+        this.position += n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// A streamed body, compressed chunk by chunk:
+struct Encode {
+    body: StreamBody,
+    encoder: Option<Encoder>,
+}
+
+impl Stream for Encode {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        let chunk = match ready!(Pin::new(&mut this.body).poll_next(cx)) {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+            None => {
+                let finished = this.encoder.take().map(Encoder::finish);
+                return Poll::Ready(finished);
+            }
+        };
+
+        match &mut this.encoder {
+            Some(encoder) => Poll::Ready(Some(encoder.encode(&chunk))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{negotiate, Compression, Decompression, Encoding};
+    use crate::app::send_request;
+    use crate::{App, Method, Request, Response, Router, Status};
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use flate2::write::GzEncoder;
+    use std::io::{Read, Write};
+    use tokio::io;
+
+    const TEXT: &str = "all work and no play makes jack a dull boy\n";
+
+    fn page(_: Request) -> Response {
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_etag("v1")
+            .with_content(TEXT.repeat(100));
+        response
+    }
+
+    fn small(_: Request) -> Response {
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "text/plain")
+            .with_content(TEXT.to_owned());
+        response
+    }
+
+    fn image(_: Request) -> Response {
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "image/png")
+            .with_content(TEXT.repeat(100));
+        response
+    }
+
+    fn stream(_: Request) -> Response {
+        let chunks = (0..100).map(|_| Ok(TEXT.as_bytes().to_vec()));
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "text/plain")
+            .with_header("Vary", "Cookie")
+            .with_body_stream(Chunks(chunks.collect()));
+        response
+    }
+
+    struct Chunks(Vec<io::Result<Vec<u8>>>);
+
+    impl futures_core::Stream for Chunks {
+        type Item = io::Result<Vec<u8>>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            let chunk = match self.0.is_empty() {
+                true => None,
+                false => Some(self.0.remove(0)),
+            };
+            std::task::Poll::Ready(chunk)
+        }
+    }
+
+    // The response and its body, with the chunked framing removed:
+    async fn get(path: &str, accept: Option<&str>) -> (Response, Vec<u8>) {
+        let mut router = Router::new();
+        router
+            .add(Method::GET, "/page", page)
+            .add(Method::GET, "/small", small)
+            .add(Method::GET, "/image", image)
+            .add(Method::GET, "/stream", stream);
+
+        let mut app = App::new(router);
+        app.with_middleware(Compression::new());
+
+        let headers = match accept {
+            Some(accept) => vec![("Accept-Encoding", accept)],
+            None => Vec::new(),
+        };
+        let mut response = send_request(&mut app, Method::GET, path, &headers)
+            .await
+            .unwrap();
+        let mut written = Vec::new();
+        response.write(&mut written).await.unwrap();
+
+        let split = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&written[..split]).into_owned();
+        let mut body = written[split + 4..].to_vec();
+
+        if head.contains("Transfer-Encoding: chunked") {
+            body = dechunk(&body);
+        }
+
+        (response, body)
+    }
+
+    fn dechunk(mut chunked: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+
+        loop {
+            let line = chunked.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = std::str::from_utf8(&chunked[..line]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            if size == 0 {
+                return body;
+            }
+
+            let data = &chunked[line + 2..];
+            body.extend_from_slice(&data[..size]);
+            chunked = &data[size + 2..];
+        }
+    }
+
+    fn gunzip(body: &[u8]) -> String {
+        let mut text = String::new();
+        GzDecoder::new(body).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, *;q=0.1"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("X-GZIP"), Some(Encoding::Gzip));
+
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("gzip;q=0.5, identity"), None);
+        assert_eq!(negotiate("gzip;q=2"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_buffered() {
+        let (response, body) = get("/page", Some("gzip")).await;
+        assert_eq!(response.header("Content-Encoding").unwrap(), "gzip");
+        assert_eq!(response.header("Vary").unwrap(), "Accept-Encoding");
+        assert_eq!(response.header("ETag").unwrap(), "W/\"v1\"");
+        assert_eq!(gunzip(&body), TEXT.repeat(100));
+
+        let (response, body) = get("/page", Some("deflate")).await;
+        assert_eq!(response.header("Content-Encoding").unwrap(), "deflate");
+        let mut text = String::new();
+        ZlibDecoder::new(&body[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, TEXT.repeat(100));
+
+        let (response, body) = get("/page", Some("br")).await;
+        assert_eq!(response.header("Content-Encoding").unwrap(), "br");
+        let mut text = String::new();
+        brotli::Decompressor::new(&body[..], 4096)
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, TEXT.repeat(100));
+    }
+
+    #[tokio::test]
+    async fn test_left_alone() {
+        let (response, body) = get("/page", None).await;
+        assert!(response.header("Content-Encoding").is_none());
+        assert_eq!(response.header("Vary").unwrap(), "Accept-Encoding");
+        assert_eq!(body, TEXT.repeat(100).into_bytes());
+
+        let (response, _) = get("/small", Some("gzip")).await;
+        assert!(response.header("Content-Encoding").is_none());
+
+        let (response, _) = get("/image", Some("gzip")).await;
+        assert!(response.header("Content-Encoding").is_none());
+        assert!(response.header("Vary").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let (response, body) = get("/stream", Some("gzip")).await;
+        assert_eq!(response.header("Content-Encoding").unwrap(), "gzip");
+        assert_eq!(response.header("Vary").unwrap(), "Cookie, Accept-Encoding");
+        assert_eq!(gunzip(&body), TEXT.repeat(100));
+    }
+
+    fn echo(request: Request) -> Response {
+        let mut response = Response::new();
+        response.with_content(request.content().clone().unwrap_or_default());
+        response
+    }
+
+    async fn upload(encoding: &str, body: &[u8], max: u64) -> Response {
+        let mut router = Router::new();
+        router.add(Method::POST, "/echo", echo);
+
+        let mut decompression = Decompression::new();
+        decompression.with_max_size(max);
+        let mut app = App::new(router);
+        app.with_middleware(decompression);
+
+        let mut request = Request::default();
+        request
+            .with_start_line(Method::POST, "/echo", "HTTP/1.1")
+            .with_header("Content-Encoding", encoding)
+            .with_header("Content-Length", &body.len().to_string())
+            .with_body(body);
+
+        app.request(request).await.unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_decompress() {
+        let text = TEXT.repeat(100);
+
+        let response = upload("gzip", &gzip(text.as_bytes()), 1 << 20).await;
+        assert_eq!(response.content(), &text);
+
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        brotli.write_all(text.as_bytes()).unwrap();
+        let response = upload("BR", &brotli.into_inner(), 1 << 20).await;
+        assert_eq!(response.content(), &text);
+
+        let response = upload("identity", text.as_bytes(), 1 << 20).await;
+        assert_eq!(response.content(), &text);
+    }
+
+    #[tokio::test]
+    async fn test_decompress_rejections() {
+        let bomb = gzip(&vec![0; 1 << 20]);
+        let response = upload("gzip", &bomb, 64 * 1024).await;
+        assert_eq!(response.status(), &Status::PayloadTooLarge);
+
+        let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        brotli.write_all(&vec![0; 1 << 20]).unwrap();
+        let response = upload("br", &brotli.into_inner(), 64 * 1024).await;
+        assert_eq!(response.status(), &Status::PayloadTooLarge);
+
+        let response = upload("gzip", b"not gzip at all", 1 << 20).await;
+        assert_eq!(response.status(), &Status::BadRequest);
+
+        let truncated = gzip(TEXT.as_bytes());
+        let truncated = &truncated[..truncated.len() - 4];
+        let response = upload("gzip", truncated, 1 << 20).await;
+        assert_eq!(response.status(), &Status::BadRequest);
+
+        let response = upload("compress", b"...", 1 << 20).await;
+        assert_eq!(response.status(), &Status::UnsupportedMediaType);
+        assert_eq!(
+            response.header("Accept-Encoding").unwrap(),
+            "br, gzip, deflate"
+        );
+    }
+}
@@ -0,0 +1,436 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::{Response, Status};
+use crate::router::BoxFuture;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+
+// Answers conditional GET requests. Buffered responses without an ETag
+// get a weak one from a hash of their content, then `If-Match`,
+// `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since` are
+// checked against the response's validators. The handler has already
+// run by then, so handlers that change state should check
+// `Request::precondition` first instead:
+#[derive(Debug, Default)]
+pub struct Conditional;
+
+impl Conditional {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Middleware for Conditional {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        let conditions = Conditions::from_request(&request);
+        let is_get = request.method() == &Method::GET;
+
+        Box::pin(async move {
+            let mut response = next.run(request).await;
+
+            if !is_get || !response.status().is_success() {
+                return response;
+            }
+
+            if response.header("ETag").is_none() && !response.is_streaming() {
+                let etag = content_etag(response.content().as_bytes());
+                response.with_header("ETag", &etag);
+            }
+
+            let etag = response.header("ETag").cloned();
+            let last_modified = response
+                .header("Last-Modified")
+                .and_then(|date| parse_http_date(date));
+
+            match conditions.evaluate(
+                &Method::GET,
+                etag.as_deref(),
+                last_modified,
+            ) {
+                Some(Status::NotModified) => response.into_not_modified(),
+                Some(status) => precondition_failed(status),
+                None => response,
+            }
+        })
+    }
+}
+
+// A weak ETag for a body, the first 128 bits of its SHA-256:
+pub fn content_etag(content: &[u8]) -> String {
+    let hash = Sha256::digest(content);
+    format!("W/\"{}\"", URL_SAFE_NO_PAD.encode(&hash[..16]))
+}
+
+pub(crate) fn precondition_failed(status: Status) -> Response {
+    let mut response = Response::new();
+    response
+        .with_status(status)
+        .with_content("Precondition failed".to_owned());
+    response
+}
+
+// The conditional headers of a request:
+#[derive(Debug, Default)]
+pub(crate) struct Conditions {
+    if_match: Option<String>,
+    if_unmodified_since: Option<DateTime<Utc>>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+}
+
+impl Conditions {
+    pub(crate) fn from_request(request: &Request) -> Self {
+        let date = |name| request.header(name).and_then(|d| parse_http_date(d));
+
+        Self {
+            if_match: request.header("If-Match").cloned(),
+            if_unmodified_since: date("If-Unmodified-Since"),
+            if_none_match: request.header("If-None-Match").cloned(),
+            if_modified_since: date("If-Modified-Since"),
+        }
+    }
+
+    // The order of RFC 9110 section 13.2.2. `None` means the request
+    // should go ahead:
+    pub(crate) fn evaluate(
+        &self,
+        method: &Method,
+        etag: Option<&str>,
+        last_modified: Option<DateTime<Utc>>,
+    ) -> Option<Status> {
+        // Dates in headers only have whole seconds:
+        let last_modified = last_modified.map(|date| date.timestamp());
+
+        if let Some(if_match) = &self.if_match {
+            if !matches_any(if_match, etag, true) {
+                return Some(Status::PreconditionFailed);
+            }
+        } else if let (Some(since), Some(modified)) =
+            (self.if_unmodified_since, last_modified)
+        {
+            if modified > since.timestamp() {
+                return Some(Status::PreconditionFailed);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if matches_any(if_none_match, etag, false) {
+                return match method {
+                    Method::GET => Some(Status::NotModified),
+                    _ => Some(Status::PreconditionFailed),
+                };
+            }
+        } else if let (Some(since), Some(modified), Method::GET) =
+            (self.if_modified_since, last_modified, method)
+        {
+            if modified <= since.timestamp() {
+                return Some(Status::NotModified);
+            }
+        }
+
+        None
+    }
+}
+
+// Whether `etag` is in a list of entity tags, or the list is `*` and
+// there is a current representation. Weak tags never match strongly:
+fn matches_any(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    let etag = match etag {
+        Some(etag) => etag.trim(),
+        None => return false,
+    };
+
+    if list.trim() == "*" {
+        return true;
+    }
+
+    let (etag_weak, etag_tag) = split_weak(etag);
+    if strong && etag_weak {
+        return false;
+    }
+
+    entity_tags(list)
+        .into_iter()
+        .any(|(weak, tag)| tag == etag_tag && !(strong && weak))
+}
+
+// Splits a list of entity tags. A quoted tag may hold commas, so the list
+// is read one tag at a time. Reading stops at anything malformed:
+fn entity_tags(list: &str) -> Vec<(bool, &str)> {
+    let mut tags = Vec::new();
+    let mut rest = list;
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            break;
+        }
+
+        let (weak, tag) = split_weak(rest);
+        let end = match tag.strip_prefix('"').and_then(|t| t.find('"')) {
+            Some(i) => i + 2,
+            None => break,
+        };
+
+        tags.push((weak, &tag[..end]));
+        rest = &tag[end..];
+    }
+
+    tags
+}
+
+fn split_weak(etag: &str) -> (bool, &str) {
+    match etag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, etag),
+    }
+}
+
+// IMF-fixdate, plus the obsolete RFC 850 and asctime formats that
+// RFC 9110 section 5.6.7 still asks recipients to accept:
+pub(crate) fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    const OBSOLETE: [&str; 2] =
+        ["%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"];
+
+    let date = date.trim();
+    if let Ok(date) = DateTime::parse_from_rfc2822(date) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    OBSOLETE
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|date| date.and_utc())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{content_etag, matches_any, parse_http_date, Conditional};
+    use crate::app::send_request;
+    use crate::response::response::http_date;
+    use crate::{App, Method, Request, Response, Router, Status};
+
+    use chrono::{TimeZone, Utc};
+
+    fn page(_: Request) -> Response {
+        let mut response = Response::new();
+        response.with_content("Hello".to_owned());
+        response
+    }
+
+    fn report(_: Request) -> Response {
+        let modified = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        let mut response = Response::new();
+        response
+            .with_etag("v2")
+            .with_last_modified(modified)
+            .with_header("Cache-Control", "max-age=60")
+            .with_content("report".to_owned());
+        response
+    }
+
+    fn update(request: Request) -> Response {
+        if let Some(status) = request.precondition(Some("\"v2\""), None) {
+            let mut response = Response::new();
+            response.with_status(status);
+            return response;
+        }
+
+        let mut response = Response::new();
+        response.with_content("updated".to_owned());
+        response
+    }
+
+    fn app() -> App {
+        let mut router = Router::new();
+        router
+            .add(Method::GET, "/page", page)
+            .add(Method::GET, "/report", report)
+            .add(Method::PATCH, "/report", update);
+
+        let mut app = App::new(router);
+        app.with_middleware(Conditional::new());
+        app
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(date), Some(expected), "{}", date);
+        }
+
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_matches_any() {
+        assert!(matches_any("\"a\", W/\"b\"", Some("W/\"b\""), false));
+        assert!(!matches_any("\"a\", W/\"b\"", Some("W/\"b\""), true));
+        assert!(matches_any("*", Some("\"a\""), true));
+        assert!(!matches_any("*", None, true));
+
+        // Commas inside a tag don't split it:
+        assert!(matches_any("\"x, y\",\"z\"", Some("\"x, y\""), true));
+        assert!(matches_any("\"x, y\",\"z\"", Some("\"z\""), true));
+        assert!(!matches_any("\"x, y\"", Some("\"x\""), true));
+        assert!(!matches_any("\"x, y\"", Some("\" y\""), true));
+    }
+
+    #[tokio::test]
+    async fn test_generated_etag() {
+        let mut app = app();
+
+        let response = send_request(&mut app, Method::GET, "/page", &[])
+            .await
+            .unwrap();
+        let etag = response.header("ETag").unwrap().clone();
+        assert_eq!(etag, content_etag(b"Hello"));
+        assert!(etag.starts_with("W/\""));
+
+        let if_none_match = format!("\"other\", {}", etag);
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/page",
+            &[("If-None-Match", &if_none_match)],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), &Status::NotModified);
+        assert_eq!(
+            response.to_string(),
+            format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\n\r\n", etag)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validators() {
+        let mut app = app();
+
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-None-Match", "W/\"v2\"")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::NotModified);
+        assert_eq!(response.header("Cache-Control").unwrap(), "max-age=60");
+        assert!(response.header("Last-Modified").is_some());
+
+        let later =
+            http_date(Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+        let earlier =
+            http_date(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-Modified-Since", &later)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::NotModified);
+
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-Modified-Since", &earlier)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.content(), "report");
+
+        // Older clients may send an obsolete date format:
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-Modified-Since", "Sat Mar  2 00:00:00 2024")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::NotModified);
+
+        // If-None-Match wins over If-Modified-Since:
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-None-Match", "\"v1\""), ("If-Modified-Since", &later)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::OK);
+
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-Unmodified-Since", &earlier)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::PreconditionFailed);
+
+        // If-Match needs a strong match:
+        let response = send_request(
+            &mut app,
+            Method::GET,
+            "/report",
+            &[("If-Match", "W/\"v2\"")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::PreconditionFailed);
+    }
+
+    #[tokio::test]
+    async fn test_precondition_before_change() {
+        let mut app = app();
+
+        let response = send_request(
+            &mut app,
+            Method::PATCH,
+            "/report",
+            &[("If-Match", "\"v1\"")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::PreconditionFailed);
+
+        let response = send_request(
+            &mut app,
+            Method::PATCH,
+            "/report",
+            &[("If-Match", "\"v2\"")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.content(), "updated");
+
+        let response = send_request(
+            &mut app,
+            Method::PATCH,
+            "/report",
+            &[("If-None-Match", "*")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), &Status::PreconditionFailed);
+    }
+}
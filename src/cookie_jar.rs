@@ -0,0 +1,419 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::{Cookies, Request};
+use crate::response::Cookie;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Length of a base64 encoded HMAC-SHA256 tag, without padding:
+const SIGNATURE_LEN: usize = 43;
+const NONCE_LEN: usize = 12;
+const MIN_SECRET_LEN: usize = 32;
+
+// The secret used to sign and encrypt cookies. Separate signing and
+// encryption keys are derived from it, so one secret can do both:
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    // The secret should be at least 32 bytes of random data, and kept
+    // the same across restarts so existing cookies stay valid:
+    pub fn try_from_secret(secret: &[u8]) -> Result<Self, InvalidKey> {
+        if secret.len() < MIN_SECRET_LEN {
+            return Err(InvalidKey(secret.len()));
+        }
+
+        Ok(Self::derive(secret))
+    }
+
+    pub fn generate() -> Self {
+        let mut secret = [0; 64];
+        OsRng.fill_bytes(&mut secret);
+
+        Self::derive(&secret)
+    }
+
+    fn derive(secret: &[u8]) -> Self {
+        Self {
+            signing: derive(secret, b"potato cookie signing"),
+            encryption: derive(secret, b"potato cookie encryption"),
+        }
+    }
+
+    fn sign(&self, name: &str, value: &str) -> String {
+        let mac = self.mac(name, value);
+        let tag = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        format!("{}{}", tag, value)
+    }
+
+    fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        if !signed.is_char_boundary(SIGNATURE_LEN) {
+            return None;
+        }
+
+        let (tag, value) = signed.split_at(SIGNATURE_LEN);
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+        // `verify_slice` compares in constant time:
+        match self.mac(name, value).verify_slice(&tag) {
+            Ok(()) => Some(value.to_owned()),
+            Err(_) => None,
+        }
+    }
+
+    // The name is part of the MAC, so a value can't be moved to another
+    // cookie:
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&self.signing).unwrap();
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn encrypt(&self, name: &str, value: &str) -> String {
+        let cipher = Aes256Gcm::new((&self.encryption).into());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let sealed = cipher.encrypt(&nonce, payload).unwrap();
+
+        let mut data = nonce.to_vec();
+        data.extend(sealed);
+
+        URL_SAFE_NO_PAD.encode(data)
+    }
+
+    fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        let data = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+
+        let (nonce, sealed) = data.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new((&self.encryption).into());
+
+        let payload = Payload {
+            msg: sealed,
+            aad: name.as_bytes(),
+        };
+        let value = cipher.decrypt(Nonce::from_slice(nonce), payload).ok()?;
+
+        String::from_utf8(value).ok()
+    }
+}
+
+// A cookie secret that is too short, with its length:
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidKey(usize);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cookie key must be at least {} bytes, got {}",
+            MIN_SECRET_LEN, self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidKey {}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key")
+    }
+}
+
+fn derive(secret: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).unwrap();
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
+
+// The key new cookies are written with, and older keys that are still
+// accepted when reading them. Set on the `App`:
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CookieKeys {
+    pub(crate) current: Option<Key>,
+    pub(crate) old: Vec<Key>,
+}
+
+impl CookieKeys {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.current.is_none() && self.old.is_empty()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Key> {
+        self.current.iter().chain(self.old.iter())
+    }
+}
+
+// Returned when adding a signed or private cookie to a jar from an `App`
+// without a cookie key:
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingCookieKey;
+
+impl fmt::Display for MissingCookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            "signed and private cookies need a key, see App::with_cookie_key",
+        )
+    }
+}
+
+impl std::error::Error for MissingCookieKey {}
+
+// The request's cookies, plus the cookies that should be set on the
+// response. Signed cookies can be read but not changed by the client,
+// private cookies can't be read either. Cookies that fail to verify are
+// treated as missing:
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Cookies,
+    keys: Option<Arc<CookieKeys>>,
+    delta: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub(crate) fn new(cookies: Cookies, keys: Option<Arc<CookieKeys>>) -> Self {
+        Self {
+            cookies,
+            keys,
+            delta: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.cookies.get(name)
+    }
+
+    pub fn get_signed(&self, name: &str) -> Option<String> {
+        let value = self.cookies.get(name)?;
+        self.keys().find_map(|key| key.verify(name, value))
+    }
+
+    pub fn get_private(&self, name: &str) -> Option<String> {
+        let value = self.cookies.get(name)?;
+        self.keys().find_map(|key| key.decrypt(name, value))
+    }
+
+    pub fn add(&mut self, cookie: Cookie) -> &mut Self {
+        self.delta.push(cookie);
+        self
+    }
+
+    pub fn add_signed(
+        &mut self,
+        mut cookie: Cookie,
+    ) -> Result<&mut Self, MissingCookieKey> {
+        let value = self.current_key()?.sign(cookie.name(), cookie.value());
+        cookie.with_value(&value);
+        Ok(self.add(cookie))
+    }
+
+    pub fn add_private(
+        &mut self,
+        mut cookie: Cookie,
+    ) -> Result<&mut Self, MissingCookieKey> {
+        let value = self.current_key()?.encrypt(cookie.name(), cookie.value());
+        cookie.with_value(&value);
+        Ok(self.add(cookie))
+    }
+
+    // Tells the client to delete the cookie. The path and domain need to
+    // match the ones it was set with:
+    pub fn remove(&mut self, cookie: Cookie) -> &mut Self {
+        self.add(cookie.into_removal())
+    }
+
+    // The cookies added or removed since the jar was created:
+    pub fn delta(&self) -> impl Iterator<Item = &Cookie> {
+        self.delta.iter()
+    }
+
+    // Without keys nothing verifies, so signed and private cookies read
+    // as missing:
+    fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.keys.iter().flat_map(|keys| keys.iter())
+    }
+
+    fn current_key(&self) -> Result<&Key, MissingCookieKey> {
+        match self.keys.as_deref() {
+            Some(CookieKeys {
+                current: Some(key), ..
+            }) => Ok(key),
+            _ => Err(MissingCookieKey),
+        }
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        Ok(request.cookie_jar())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CookieJar, CookieKeys, InvalidKey, Key, MissingCookieKey};
+    use crate::request::Cookies;
+    use crate::response::Cookie;
+
+    use std::sync::Arc;
+
+    fn keys(current: &Key, old: &[Key]) -> Option<Arc<CookieKeys>> {
+        Some(Arc::new(CookieKeys {
+            current: Some(current.clone()),
+            old: old.to_vec(),
+        }))
+    }
+
+    // Sends the jar's cookies back, as a browser would:
+    fn round_trip(jar: &CookieJar, keys: Option<Arc<CookieKeys>>) -> CookieJar {
+        let header = jar
+            .delta()
+            .map(|c| format!("{}={}", c.name(), c.value()))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        CookieJar::new(Cookies::parse(&header), keys)
+    }
+
+    #[test]
+    fn test_signed() {
+        let key = Key::generate();
+
+        let mut jar = CookieJar::new(Cookies::default(), keys(&key, &[]));
+        jar.add_signed(Cookie::new("user", "bob").unwrap()).unwrap();
+
+        let value = jar.delta().next().unwrap().value();
+        assert!(value.ends_with("bob"));
+        assert_eq!(value.len(), 43 + 3);
+
+        let jar = round_trip(&jar, keys(&key, &[]));
+        assert_eq!(jar.get_signed("user"), Some("bob".to_owned()));
+
+        // Changing the value, or moving it to another cookie, breaks it:
+        let tampered = jar.get("user").unwrap().replace("bob", "eve");
+        let header =
+            format!("user={}; admin={}", tampered, jar.get("user").unwrap());
+        let jar = CookieJar::new(Cookies::parse(&header), keys(&key, &[]));
+        assert_eq!(jar.get_signed("user"), None);
+        assert_eq!(jar.get_signed("admin"), None);
+    }
+
+    #[test]
+    fn test_private() {
+        let key = Key::generate();
+
+        let mut jar = CookieJar::new(Cookies::default(), keys(&key, &[]));
+        jar.add_private(Cookie::new("session", "secret id").unwrap())
+            .unwrap();
+
+        let value = jar.delta().next().unwrap().value();
+        assert!(!value.contains("secret"));
+
+        let jar = round_trip(&jar, keys(&key, &[]));
+        assert_eq!(jar.get_private("session"), Some("secret id".to_owned()));
+        assert_eq!(jar.get_signed("session"), None);
+
+        let jar = round_trip(&jar, keys(&Key::generate(), &[]));
+        assert_eq!(jar.get_private("session"), None);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = Key::try_from_secret(&[1; 32]).unwrap();
+        let new = Key::try_from_secret(&[2; 32]).unwrap();
+
+        let mut jar = CookieJar::new(Cookies::default(), keys(&old, &[]));
+        jar.add_signed(Cookie::new("a", "1").unwrap())
+            .unwrap()
+            .add_private(Cookie::new("b", "2").unwrap())
+            .unwrap();
+
+        let jar = round_trip(&jar, keys(&new, &[old]));
+        assert_eq!(jar.get_signed("a"), Some("1".to_owned()));
+        assert_eq!(jar.get_private("b"), Some("2".to_owned()));
+
+        let jar = round_trip(&jar, keys(&new, &[]));
+        assert_eq!(jar.get_signed("a"), None);
+        assert_eq!(jar.get_private("b"), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut cookie = Cookie::new("session", "abc").unwrap();
+        cookie.with_path("/app");
+
+        let mut jar = CookieJar::default();
+        jar.remove(cookie);
+
+        assert_eq!(
+            jar.delta().next().unwrap().to_string(),
+            "session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; \
+             Path=/app"
+        );
+    }
+
+    #[test]
+    fn test_short_secret() {
+        let error = Key::try_from_secret(&[1; 31]).unwrap_err();
+        assert_eq!(error, InvalidKey(31));
+        assert_eq!(
+            error.to_string(),
+            "cookie key must be at least 32 bytes, got 31"
+        );
+    }
+
+    #[test]
+    fn test_without_key() {
+        let mut jar = CookieJar::default();
+        let cookie = Cookie::new("user", "bob").unwrap();
+
+        assert_eq!(
+            jar.add_signed(cookie.clone()).err(),
+            Some(MissingCookieKey)
+        );
+        assert_eq!(jar.add_private(cookie).err(), Some(MissingCookieKey));
+        assert_eq!(jar.delta().count(), 0);
+
+        let jar = CookieJar::new(Cookies::parse("user=bob"), None);
+        assert_eq!(jar.get_signed("user"), None);
+        assert_eq!(jar.get_private("user"), None);
+    }
+
+    #[test]
+    fn test_only_old_keys() {
+        let old = Key::generate();
+
+        let mut jar = CookieJar::new(Cookies::default(), keys(&old, &[]));
+        jar.add_signed(Cookie::new("a", "1").unwrap()).unwrap();
+
+        let keys = Some(Arc::new(CookieKeys {
+            current: None,
+            old: vec![old],
+        }));
+        let mut jar = round_trip(&jar, keys);
+        assert_eq!(jar.get_signed("a"), Some("1".to_owned()));
+        assert!(jar.add_signed(Cookie::new("b", "2").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::request::Request;
+use crate::response::{Response, Status};
+
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, Rejection>;
+}
+
+// An extractor failure. Converting it into a Response gives the status
+// with the message as plain text content:
+#[derive(Debug)]
+pub struct Rejection {
+    status: Status,
+    message: String,
+}
+
+impl Rejection {
+    pub fn new(status: Status, message: &str) -> Self {
+        Self {
+            status,
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    pub fn message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl From<Rejection> for Response {
+    fn from(rejection: Rejection) -> Self {
+        let mut response = Response::new();
+        response
+            .with_status(rejection.status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_content(rejection.message);
+
+        response
+    }
+}
+
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        request.query_as().map(Query)
+    }
+}
+
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        request.form_as().map(Form)
+    }
+}
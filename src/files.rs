@@ -0,0 +1,324 @@
+use crate::conditional::{precondition_failed, Conditions};
+use crate::redirect::{same_site, Redirect};
+use crate::request::query::percent_decode;
+use crate::request::{Method, Request};
+use crate::response::{Response, Status};
+use crate::router::{BoxFuture, Endpoint};
+
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+
+// Serves the files in a directory, mounted with `Router::mount`. The
+// path below the mount prefix picks the file, `index.html` stands in
+// for a directory. Files are streamed from disk:
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    index: Option<String>,
+}
+
+impl ServeDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index: Some("index.html".to_owned()),
+        }
+    }
+
+    // The file served for a directory, `None` to answer 404 instead:
+    pub fn with_index(&mut self, index: Option<&str>) -> &mut Self {
+        self.index = index.map(|index| index.to_owned());
+        self
+    }
+
+    async fn serve(
+        root: PathBuf,
+        index: Option<String>,
+        request: Request,
+    ) -> Response {
+        if request.method() != &Method::GET {
+            return method_not_allowed();
+        }
+
+        let path = match resolve(&root, request.mount_path()) {
+            Some(path) => path,
+            None => return not_found(),
+        };
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return not_found(),
+        };
+
+        if !metadata.is_dir() {
+            return send_file(&path, Conditions::from_request(&request)).await;
+        }
+
+        // Links in the index page are relative to the directory, so the
+        // URL has to end in a slash:
+        if !request.path().ends_with('/') {
+            let path = format!("{}/", request.path());
+            let location = same_site(&path, &request);
+            return Response::redirect(&location, Redirect::Permanent);
+        }
+
+        match index {
+            Some(index) => {
+                let conditions = Conditions::from_request(&request);
+                send_file(&path.join(index), conditions).await
+            }
+            None => not_found(),
+        }
+    }
+}
+
+impl Endpoint for ServeDir {
+    fn call(&self, request: Request) -> BoxFuture<Response> {
+        Box::pin(Self::serve(self.root.clone(), self.index.clone(), request))
+    }
+}
+
+// Serves one file, whatever the path below its mount prefix:
+#[derive(Debug, Clone)]
+pub struct ServeFile {
+    path: PathBuf,
+}
+
+impl ServeFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Endpoint for ServeFile {
+    fn call(&self, request: Request) -> BoxFuture<Response> {
+        let path = self.path.clone();
+        let conditions = Conditions::from_request(&request);
+        let is_get = request.method() == &Method::GET;
+
+        Box::pin(async move {
+            match is_get {
+                true => send_file(&path, conditions).await,
+                false => method_not_allowed(),
+            }
+        })
+    }
+}
+
+// Maps a URL path onto `root`. Paths that would leave it are refused:
+fn resolve(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+
+    for segment in percent_decode(url_path).split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            s if s.contains(['\\', '\0']) || Path::new(s).has_root() => {
+                return None
+            }
+            s => path.push(s),
+        }
+    }
+
+    Some(path)
+}
+
+// Conditional headers are checked here, as the file's validators are
+// only known once it is opened:
+async fn send_file(path: &Path, conditions: Conditions) -> Response {
+    let file = match File::open(path).await {
+        Ok(file) => file,
+        Err(_) => return not_found(),
+    };
+
+    let metadata = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return not_found(),
+    };
+
+    let content_type = mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string();
+
+    // Changes to a file show in its size or modification time:
+    let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+    let version = modified.map_or(0, |date| date.timestamp());
+    let etag = format!("{:x}-{:x}", metadata.len(), version);
+
+    let mut response = Response::new();
+    response.with_etag(&etag);
+    if let Some(modified) = modified {
+        response.with_last_modified(modified);
+    }
+
+    response
+        .with_header("Content-Type", &content_type)
+        .with_body_seekable(file, metadata.len());
+
+    let etag = response.header("ETag").cloned();
+    match conditions.evaluate(&Method::GET, etag.as_deref(), modified) {
+        Some(Status::NotModified) => response.into_not_modified(),
+        Some(status) => precondition_failed(status),
+        None => response,
+    }
+}
+
+fn not_found() -> Response {
+    let mut response = Response::new();
+    response
+        .with_status(Status::NotFound)
+        .with_content("Not found".to_owned());
+    response
+}
+
+fn method_not_allowed() -> Response {
+    let mut response = Response::new();
+    response
+        .with_status(Status::MethodNotAllowed)
+        .with_header("Allow", "GET")
+        .with_content("Method not allowed".to_owned());
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve, ServeDir, ServeFile};
+    use crate::app::send_request;
+    use crate::{App, Method, Router, Status};
+
+    use std::path::{Path, PathBuf};
+
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "potato-{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("style.css"), "body {}").unwrap();
+        std::fs::write(dir.join("docs/index.html"), "<h1>Docs</h1>").unwrap();
+        std::fs::write(dir.join("data.unknownext"), [0u8, 1, 2]).unwrap();
+
+        dir
+    }
+
+    async fn get(app: &mut App, target: &str) -> (Status, String) {
+        get_with(app, target, &[]).await
+    }
+
+    async fn get_with(
+        app: &mut App,
+        target: &str,
+        headers: &[(&str, &str)],
+    ) -> (Status, String) {
+        let response = send_request(app, Method::GET, target, headers).await;
+        let mut response = match response {
+            Ok(response) => response,
+            Err(status) => return (status, String::new()),
+        };
+        let mut written = Vec::new();
+        response.write(&mut written).await.unwrap();
+
+        let written = String::from_utf8_lossy(&written).into_owned();
+        (*response.status(), written)
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("/srv");
+
+        assert_eq!(resolve(root, "/a/./b"), Some(PathBuf::from("/srv/a/b")));
+        assert_eq!(resolve(root, "/a/%2E%2E/%2E%2E/etc/passwd"), None);
+        assert_eq!(resolve(root, "/../etc/passwd"), None);
+        assert_eq!(resolve(root, "/a\\..\\b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_dir() {
+        let dir = fixture("files");
+
+        let mut router = Router::new();
+        router
+            .mount("/static", ServeDir::new(&dir))
+            .mount("/favicon.ico", ServeFile::new(dir.join("style.css")));
+        let mut app = App::new(router);
+
+        let (status, written) = get(&mut app, "/static/style.css").await;
+        assert_eq!(status, Status::OK);
+        assert!(written.contains("Content-Type: text/css\r\n"));
+        assert!(written.contains("Content-Length: 7\r\n"));
+        assert!(written.ends_with("\r\n\r\nbody {}"));
+        assert!(written.contains("Accept-Ranges: bytes\r\n"));
+        assert!(written.contains("ETag: \"7-"));
+        assert!(written.contains("Last-Modified: "));
+
+        let (_, written) = get(&mut app, "/static/docs/").await;
+        assert!(written.contains("Content-Type: text/html\r\n"));
+        assert!(written.ends_with("<h1>Docs</h1>"));
+
+        let (status, written) = get(&mut app, "/static/docs?page=2").await;
+        assert_eq!(status, Status::PermanentRedirect);
+        assert!(written.contains("Location: /static/docs/?page=2\r\n"));
+
+        let (_, written) = get(&mut app, "/static/data.unknownext").await;
+        assert!(written.contains("application/octet-stream"));
+
+        let (status, _) = get(&mut app, "/static/../Cargo.toml").await;
+        assert_eq!(status, Status::NotFound);
+
+        let (status, _) = get(&mut app, "/staticfile").await;
+        assert_eq!(status, Status::NotFound);
+
+        let (status, _) = get(&mut app, "/favicon.ico").await;
+        assert_eq!(status, Status::OK);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_conditional() {
+        let dir = fixture("conditional-files");
+
+        let mut router = Router::new();
+        router.mount("/static", ServeDir::new(&dir));
+        let mut app = App::new(router);
+
+        let (_, written) = get(&mut app, "/static/style.css").await;
+        let etag = written
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap()
+            .to_owned();
+
+        let (status, written) = get_with(
+            &mut app,
+            "/static/style.css",
+            &[("If-None-Match", &etag)],
+        )
+        .await;
+        assert_eq!(status, Status::NotModified);
+        assert!(written.contains(&format!("ETag: {}\r\n", etag)));
+        assert!(written.ends_with("\r\n\r\n"));
+
+        let (status, _) = get_with(
+            &mut app,
+            "/static/style.css",
+            &[("If-Match", "\"other\"")],
+        )
+        .await;
+        assert_eq!(status, Status::PreconditionFailed);
+
+        let (status, written) = get_with(
+            &mut app,
+            "/static/style.css",
+            &[("If-None-Match", "\"other\"")],
+        )
+        .await;
+        assert_eq!(status, Status::OK);
+        assert!(written.ends_with("body {}"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
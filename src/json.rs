@@ -0,0 +1,106 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::Request;
+use crate::response::{Response, Status};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// Extracts a JSON request body, or serializes a value into a JSON
+// response:
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        request.json().map(Json)
+    }
+}
+
+impl<T: Serialize> From<Json<T>> for Response {
+    fn from(json: Json<T>) -> Self {
+        let mut response = Response::new();
+
+        match serde_json::to_string(&json.0) {
+            Ok(content) => response
+                .with_header("Content-Type", "application/json")
+                .with_content(content),
+            Err(_) => response
+                .with_status(Status::Internal)
+                .with_content("Internal Server Error".to_owned()),
+        };
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Json;
+    use crate::{Method, Request, Response, Status};
+
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Potato {
+        name: String,
+        weight: u32,
+    }
+
+    fn request(content_type: &str, content: &str) -> Request {
+        let mut request = Request::default();
+        request
+            .with_start_line(Method::POST, "/potato", "HTTP/1.1")
+            .with_header("content-type", content_type)
+            .with_content(content);
+
+        request
+    }
+
+    #[test]
+    fn test_extract() {
+        let request = request(
+            "application/json; charset=utf-8",
+            r#"{"name": "russet", "weight": 200}"#,
+        );
+
+        let Json(potato) = request.extract::<Json<Potato>>().unwrap();
+        assert_eq!(
+            potato,
+            Potato {
+                name: "russet".to_owned(),
+                weight: 200
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejections() {
+        let rejection =
+            request("text/plain", "{}").json::<Potato>().unwrap_err();
+        assert_eq!(rejection.status(), &Status::UnsupportedMediaType);
+
+        let rejection = request("application/json", r#"{"name": "russet"}"#)
+            .json::<Potato>()
+            .unwrap_err();
+        assert_eq!(rejection.status(), &Status::UnprocessableEntity);
+
+        let rejection = request("application/json", r#"{"name": "#)
+            .json::<Potato>()
+            .unwrap_err();
+        assert_eq!(rejection.status(), &Status::BadRequest);
+    }
+
+    #[test]
+    fn test_response() {
+        let response: Response = Json(Potato {
+            name: "russet".to_owned(),
+            weight: 200,
+        })
+        .into();
+
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some(&"application/json".to_owned())
+        );
+        assert_eq!(response.content(), r#"{"name":"russet","weight":200}"#);
+    }
+}
@@ -1,9 +1,37 @@
 pub mod app;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod conditional;
+pub mod cookie_jar;
+pub mod extract;
+pub mod files;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod middleware;
+mod range;
+pub mod redirect;
 pub mod request;
 pub mod response;
 pub mod router;
+pub mod session;
+pub mod sse;
+pub mod upgrade;
+pub mod websocket;
 
 pub use app::App;
-pub use request::{Method, Request};
+#[cfg(feature = "compression")]
+pub use compression::{Compression, Decompression};
+pub use conditional::Conditional;
+pub use cookie_jar::{CookieJar, InvalidKey, Key, MissingCookieKey};
+pub use files::{ServeDir, ServeFile};
+#[cfg(feature = "json")]
+pub use json::Json;
+pub use middleware::{Middleware, Next};
+pub use redirect::Redirect;
+pub use request::{Method, Query, Request};
 pub use response::{Cookie, Response, Status};
-pub use router::Router;
+pub use router::{Endpoint, Router, TrailingSlash};
+pub use session::{Session, Sessions};
+pub use sse::{Event, Sse};
+pub use upgrade::Upgraded;
+pub use websocket::{Message, WebSocket, WebSocketUpgrade};
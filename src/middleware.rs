@@ -0,0 +1,85 @@
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::router::{BoxFuture, Handler};
+
+use std::sync::Arc;
+
+// Middleware wraps every request, before routing. It runs before the
+// request body has been read, so rejecting a request without calling
+// `next` means the body is never read, and a client waiting on
+// `Expect: 100-continue` never sends it:
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next) -> BoxFuture<Response> + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        self(request, next)
+    }
+}
+
+// The rest of the chain: the remaining middleware, then the router:
+#[derive(Clone)]
+pub struct Next {
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    index: usize,
+    endpoint: Handler,
+}
+
+impl Next {
+    pub(crate) fn new(
+        middleware: Arc<[Arc<dyn Middleware>]>,
+        endpoint: Handler,
+    ) -> Self {
+        Self {
+            middleware,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    pub fn run(mut self, request: Request) -> BoxFuture<Response> {
+        match self.middleware.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.handle(request, self)
+            }
+            None => (self.endpoint)(request),
+        }
+    }
+}
+
+// Rejects requests whose Content-Length is above `max` bytes with
+// 413 Payload Too Large, without reading the body. Bodies read into
+// memory are limited to `max` bytes as well, however they were sent:
+pub struct BodyLimit {
+    max: usize,
+}
+
+impl BodyLimit {
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl Middleware for BodyLimit {
+    fn handle(&self, mut request: Request, next: Next) -> BoxFuture<Response> {
+        let length = request.content_length().unwrap_or(0);
+
+        if length <= self.max {
+            request.with_body_limit(self.max);
+            return next.run(request);
+        }
+
+        Box::pin(async move {
+            let mut response = Response::new();
+            response
+                .with_status(Status::PayloadTooLarge)
+                .with_content("Payload too large".to_owned());
+            response
+        })
+    }
+}
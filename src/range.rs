@@ -0,0 +1,325 @@
+use crate::conditional::parse_http_date;
+use crate::middleware::{Middleware, Next};
+use crate::request::{Method, Request};
+use crate::response::body::Part;
+use crate::response::{Response, Status};
+use crate::router::BoxFuture;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::ops::Range;
+
+// More ranges than this are answered with the whole body, rather than
+// seeking all over it:
+const MAX_RANGES: usize = 16;
+
+// Answers `Range` requests for responses with a seekable body. The app
+// puts it in front of all other middleware:
+pub(crate) struct Ranges;
+
+impl Middleware for Ranges {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        let range = match request.method() {
+            Method::GET => request.header("Range").cloned(),
+            _ => None,
+        };
+        let if_range = request.header("If-Range").cloned();
+
+        Box::pin(async move {
+            let mut response = next.run(request).await;
+
+            let length = match response.seekable_length() {
+                Some(length) if response.status() == &Status::OK => length,
+                _ => return response,
+            };
+            response.with_header("Accept-Ranges", "bytes");
+
+            let range = match range {
+                Some(range) => range,
+                None => return response,
+            };
+
+            if !if_range_holds(if_range.as_deref(), &response) {
+                return response;
+            }
+
+            match parse_ranges(&range, length) {
+                Some(ranges) if ranges.is_empty() => unsatisfiable(length),
+                Some(ranges) if ranges.len() <= MAX_RANGES => {
+                    partial(response, ranges, length)
+                }
+                _ => response,
+            }
+        })
+    }
+}
+
+// `If-Range` asks for the ranges only while the representation is the
+// one the client already has part of, otherwise for all of it. Only
+// strong validators can tell:
+fn if_range_holds(if_range: Option<&str>, response: &Response) -> bool {
+    let if_range = match if_range {
+        Some(if_range) => if_range.trim(),
+        None => return true,
+    };
+
+    if if_range.starts_with('"') {
+        let etag = response.header("ETag").map(|etag| etag.trim());
+        return etag == Some(if_range);
+    }
+
+    let last_modified = response
+        .header("Last-Modified")
+        .and_then(|date| parse_http_date(date));
+
+    match (parse_http_date(if_range), last_modified) {
+        (Some(date), Some(last_modified)) => date == last_modified,
+        _ => false,
+    }
+}
+
+// The byte ranges of a `Range` header, clamped to the body. `None` if
+// the header can't be parsed, so it is ignored. No ranges means none of
+// them could be satisfied:
+fn parse_ranges(header: &str, length: u64) -> Option<Vec<Range<u64>>> {
+    let (unit, specs) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    let mut specs_seen = 0;
+
+    for spec in specs.split(',').map(str::trim) {
+        if spec.is_empty() {
+            continue;
+        }
+        specs_seen += 1;
+
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix = parse_position(suffix)?;
+                length.saturating_sub(suffix)..length
+            }
+            (first, "") => parse_position(first)?..length,
+            (first, last) => {
+                let (first, last) =
+                    (parse_position(first)?, parse_position(last)?);
+                if last < first {
+                    return None;
+                }
+                first..last.saturating_add(1).min(length)
+            }
+        };
+
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    match specs_seen {
+        0 => None,
+        _ => Some(ranges),
+    }
+}
+
+fn parse_position(position: &str) -> Option<u64> {
+    match position.bytes().all(|b| b.is_ascii_digit()) {
+        true => position.parse().ok(),
+        false => None,
+    }
+}
+
+fn content_range(range: &Range<u64>, length: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, length)
+}
+
+// One range is sent as is, several as the parts of a
+// `multipart/byteranges` body:
+fn partial(
+    mut response: Response,
+    mut ranges: Vec<Range<u64>>,
+    length: u64,
+) -> Response {
+    let body = match response.take_seekable() {
+        Some(body) => body,
+        None => return response,
+    };
+
+    response.with_status(Status::PartialContent);
+
+    if ranges.len() == 1 {
+        let range = ranges.remove(0);
+        let range_length = range.end - range.start;
+
+        response
+            .with_header("Content-Range", &content_range(&range, length))
+            .with_body_reader(body.into_reader(vec![Part::Range(range)]))
+            .with_body_length(range_length);
+        return response;
+    }
+
+    let mut boundary = [0; 18];
+    OsRng.fill_bytes(&mut boundary);
+    let boundary = URL_SAFE_NO_PAD.encode(boundary);
+
+    let content_type = response.header("Content-Type").cloned();
+    let mut parts = Vec::new();
+
+    for (i, range) in ranges.into_iter().enumerate() {
+        let mut head = match i {
+            0 => format!("--{}\r\n", boundary),
+            _ => format!("\r\n--{}\r\n", boundary),
+        };
+        if let Some(content_type) = &content_type {
+            head.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        head.push_str(&format!(
+            "Content-Range: {}\r\n\r\n",
+            content_range(&range, length)
+        ));
+
+        parts.push(Part::Bytes(head.into_bytes()));
+        parts.push(Part::Range(range));
+    }
+    parts.push(Part::Bytes(format!("\r\n--{}--\r\n", boundary).into()));
+
+    let body_length = parts.iter().map(Part::len).sum();
+    let content_type = format!("multipart/byteranges; boundary={}", boundary);
+
+    response
+        .with_header("Content-Type", &content_type)
+        .with_body_reader(body.into_reader(parts))
+        .with_body_length(body_length);
+    response
+}
+
+fn unsatisfiable(length: u64) -> Response {
+    let mut response = Response::new();
+    response
+        .with_status(Status::RangeNotSatisfiable)
+        .with_header("Content-Range", &format!("bytes */{}", length))
+        .with_content("Range not satisfiable".to_owned());
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_ranges;
+    use crate::app::send_request;
+    use crate::{App, Method, Request, Response, Router, Status};
+
+    use std::io::Cursor;
+    use std::ops::Range;
+
+    fn digits(_: Request) -> Response {
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "text/plain")
+            .with_etag("v1")
+            .with_body_seekable(Cursor::new(b"0123456789".to_vec()), 10);
+        response
+    }
+
+    async fn get(headers: &[(&str, &str)]) -> (Response, String) {
+        let mut router = Router::new();
+        router.add(Method::GET, "/digits", digits);
+        let mut app = App::new(router);
+
+        let mut response =
+            send_request(&mut app, Method::GET, "/digits", headers)
+                .await
+                .unwrap();
+        let mut written = Vec::new();
+        response.write(&mut written).await.unwrap();
+
+        let written = String::from_utf8(written).unwrap();
+        let body = written.split_once("\r\n\r\n").unwrap().1.to_owned();
+        (response, body)
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let one = |range: Range<u64>| Some(vec![range]);
+
+        assert_eq!(parse_ranges("bytes=0-4", 10), one(0..5));
+        assert_eq!(parse_ranges("bytes=-3", 10), one(7..10));
+        assert_eq!(parse_ranges("bytes=8-", 10), one(8..10));
+        assert_eq!(parse_ranges("bytes=5-100", 10), one(5..10));
+        assert_eq!(parse_ranges("bytes= 0-0, -1", 10), Some(vec![0..1, 9..10]));
+
+        assert_eq!(parse_ranges("bytes=10-", 10), Some(vec![]));
+        assert_eq!(parse_ranges("bytes=-0", 10), Some(vec![]));
+
+        assert_eq!(parse_ranges("bytes=4-2", 10), None);
+        assert_eq!(parse_ranges("bytes=a-b", 10), None);
+        assert_eq!(parse_ranges("bytes=+1-2", 10), None);
+        assert_eq!(parse_ranges("lines=0-4", 10), None);
+        assert_eq!(parse_ranges("bytes=", 10), None);
+    }
+
+    #[tokio::test]
+    async fn test_ranges() {
+        let (response, body) = get(&[]).await;
+        assert_eq!(response.status(), &Status::OK);
+        assert_eq!(response.header("Accept-Ranges").unwrap(), "bytes");
+        assert_eq!(body, "0123456789");
+
+        let (response, body) = get(&[("Range", "bytes=2-4")]).await;
+        assert_eq!(response.status(), &Status::PartialContent);
+        assert_eq!(response.header("Content-Range").unwrap(), "bytes 2-4/10");
+        assert_eq!(body, "234");
+
+        let (response, _) = get(&[("Range", "bytes=20-")]).await;
+        assert_eq!(response.status(), &Status::RangeNotSatisfiable);
+        assert_eq!(response.header("Content-Range").unwrap(), "bytes */10");
+
+        // An unparsable range is ignored:
+        let (response, _) = get(&[("Range", "bytes=x")]).await;
+        assert_eq!(response.status(), &Status::OK);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_ranges() {
+        let (response, body) = get(&[("Range", "bytes=0-1,-2")]).await;
+        assert_eq!(response.status(), &Status::PartialContent);
+
+        let content_type = response.header("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+
+        assert_eq!(
+            body,
+            format!(
+                "--{b}\r\nContent-Type: text/plain\r\n\
+                 Content-Range: bytes 0-1/10\r\n\r\n01\r\n\
+                 --{b}\r\nContent-Type: text/plain\r\n\
+                 Content-Range: bytes 8-9/10\r\n\r\n89\r\n\
+                 --{b}--\r\n",
+                b = boundary
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_if_range() {
+        let (response, body) =
+            get(&[("Range", "bytes=0-1"), ("If-Range", "\"v1\"")]).await;
+        assert_eq!(response.status(), &Status::PartialContent);
+        assert_eq!(body, "01");
+
+        let (response, body) =
+            get(&[("Range", "bytes=0-1"), ("If-Range", "\"v0\"")]).await;
+        assert_eq!(response.status(), &Status::OK);
+        assert_eq!(body, "0123456789");
+
+        // A weak tag can't be used for ranges:
+        let (response, _) =
+            get(&[("Range", "bytes=0-1"), ("If-Range", "W/\"v1\"")]).await;
+        assert_eq!(response.status(), &Status::OK);
+    }
+}
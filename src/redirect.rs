@@ -0,0 +1,341 @@
+use crate::middleware::{Middleware, Next};
+use crate::request::query::is_hex_pair;
+use crate::request::Request;
+use crate::response::{Response, Status};
+use crate::router::BoxFuture;
+
+// The kinds of redirect. `SeeOther` makes the client follow up with a
+// GET, `Temporary` and `Permanent` keep the method and body. Browsers
+// treat `MovedPermanently` and `Found` like `SeeOther` for a POST.
+// Permanent redirects may be cached:
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redirect {
+    MovedPermanently,
+    Found,
+    SeeOther,
+    Temporary,
+    Permanent,
+}
+
+impl Redirect {
+    pub fn status(&self) -> Status {
+        match self {
+            Redirect::MovedPermanently => Status::MovedPermanently,
+            Redirect::Found => Status::Found,
+            Redirect::SeeOther => Status::SeeOther,
+            Redirect::Temporary => Status::TemporaryRedirect,
+            Redirect::Permanent => Status::PermanentRedirect,
+        }
+    }
+}
+
+// Percent-encodes whatever can't appear in a URL, like spaces and
+// non-ASCII characters. Reserved characters and escapes that are already
+// there are left alone:
+pub fn encode_location(location: &str) -> String {
+    let bytes = location.as_bytes();
+    let mut encoded = String::with_capacity(bytes.len());
+
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'%' if is_hex_pair(&bytes[i + 1..]) => encoded.push('%'),
+            b if b.is_ascii_alphanumeric()
+                || b"-._~:/?#[]@!$&'()*+,;=".contains(&b) =>
+            {
+                encoded.push(b as char)
+            }
+            b => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    encoded
+}
+
+// A location on this site for `path` and the query of `request`. Leading
+// slashes are collapsed, `//host/path` would send the client elsewhere:
+pub(crate) fn same_site(path: &str, request: &Request) -> String {
+    let mut location = format!("/{}", path.trim_start_matches('/'));
+
+    if let Some((_, query)) = request.target().split_once('?') {
+        location = format!("{}?{}", location, query);
+    }
+
+    location
+}
+
+// Sends plain HTTP requests to the same URL over HTTPS. The app itself
+// only speaks plain HTTP, so requests that reached it through a proxy
+// that terminates TLS are told apart by `X-Forwarded-Proto` or
+// `Forwarded`, and go through:
+#[derive(Debug, Default)]
+pub struct HttpsRedirect {
+    port: Option<u16>,
+}
+
+impl HttpsRedirect {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The HTTPS port, if it isn't 443:
+    pub fn with_port(&mut self, port: u16) -> &mut Self {
+        self.port = Some(port);
+        self
+    }
+}
+
+impl Middleware for HttpsRedirect {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        if is_https(&request) {
+            return next.run(request);
+        }
+
+        let host = match request.header("Host").and_then(|h| hostname(h)) {
+            Some(host) => host,
+            None => {
+                return Box::pin(async {
+                    let mut response = Response::new();
+                    response
+                        .with_status(Status::BadRequest)
+                        .with_content("Bad request".to_owned());
+                    response
+                })
+            }
+        };
+
+        let port = match self.port {
+            Some(port) if port != 443 => format!(":{}", port),
+            _ => String::new(),
+        };
+        let location = format!(
+            "https://{}{}{}",
+            host,
+            port,
+            same_site(request.path(), &request)
+        );
+
+        Box::pin(
+            async move { Response::redirect(&location, Redirect::Permanent) },
+        )
+    }
+}
+
+fn is_https(request: &Request) -> bool {
+    if let Some(proto) = request.header("X-Forwarded-Proto") {
+        let first = proto.split(',').next().unwrap_or("");
+        return first.trim().eq_ignore_ascii_case("https");
+    }
+
+    let forwarded = match request.header("Forwarded") {
+        Some(forwarded) => forwarded,
+        None => return false,
+    };
+
+    // The first element was added by the proxy closest to the client:
+    let first = forwarded.split(',').next().unwrap_or("");
+    first.split(';').any(|pair| match pair.split_once('=') {
+        Some((key, value)) => {
+            key.trim().eq_ignore_ascii_case("proto")
+                && value.trim().trim_matches('"').eq_ignore_ascii_case("https")
+        }
+        None => false,
+    })
+}
+
+// The Host header without its port. Anything that isn't a plain host
+// name or address is refused, so it can't change where we redirect:
+fn hostname(host: &str) -> Option<&str> {
+    let host = host.trim();
+
+    let name = match host.strip_prefix('[') {
+        Some(rest) => &host[..rest.find(']')? + 2],
+        None => host.split(':').next().unwrap_or(""),
+    };
+
+    let valid = !name.is_empty()
+        && name.bytes().all(|b| {
+            b.is_ascii_alphanumeric()
+                || matches!(b, b'.' | b'-' | b'[' | b']' | b':')
+        });
+
+    match valid {
+        true => Some(name),
+        false => None,
+    }
+}
+
+// Redirects paths to one spelling, with or without a trailing slash,
+// so each page has a single URL. The root is left alone, and so are
+// paths ending in something that looks like a file name when slashes
+// are added:
+#[derive(Debug)]
+pub struct TrailingSlashRedirect {
+    append: bool,
+}
+
+impl TrailingSlashRedirect {
+    // `/potato/` goes to `/potato`:
+    pub fn trim() -> Self {
+        Self { append: false }
+    }
+
+    // `/potato` goes to `/potato/`:
+    pub fn append() -> Self {
+        Self { append: true }
+    }
+}
+
+impl Middleware for TrailingSlashRedirect {
+    fn handle(&self, request: Request, next: Next) -> BoxFuture<Response> {
+        let path = request.path();
+
+        let canonical = match self.append {
+            false if path.len() > 1 && path.ends_with('/') => {
+                path.trim_end_matches('/').to_owned()
+            }
+            true if !path.ends_with('/') && !looks_like_file(path) => {
+                format!("{}/", path)
+            }
+            _ => return next.run(request),
+        };
+
+        let location = same_site(&canonical, &request);
+        Box::pin(
+            async move { Response::redirect(&location, Redirect::Permanent) },
+        )
+    }
+}
+
+fn looks_like_file(path: &str) -> bool {
+    let last = path.rsplit('/').next().unwrap_or("");
+    last.contains('.')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        encode_location, hostname, HttpsRedirect, Redirect,
+        TrailingSlashRedirect,
+    };
+    use crate::app::send_request;
+    use crate::{App, Method, Request, Response, Router, Status};
+
+    fn ok(_: Request) -> Response {
+        Response::new()
+    }
+
+    async fn get(
+        app: &mut App,
+        target: &str,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        send_request(app, Method::GET, target, headers)
+            .await
+            .unwrap()
+    }
+
+    fn build(middleware: impl crate::Middleware) -> App {
+        let mut router = Router::new();
+        router
+            .add(Method::GET, "/", ok)
+            .add(Method::GET, "/potato", ok)
+            .add(Method::GET, "/style.css", ok);
+
+        let mut app = App::new(router);
+        app.with_middleware(middleware);
+        app
+    }
+
+    #[test]
+    fn test_encode_location() {
+        assert_eq!(
+            encode_location("/a b/ü?x=1&y=%2F"),
+            "/a%20b/%C3%BC?x=1&y=%2F"
+        );
+        assert_eq!(
+            encode_location("https://example.com/#top"),
+            "https://example.com/#top"
+        );
+        assert_eq!(
+            encode_location("/\r\nSet-Cookie: x"),
+            "/%0D%0ASet-Cookie:%20x"
+        );
+        assert_eq!(encode_location("/100%"), "/100%25");
+    }
+
+    #[test]
+    fn test_redirect() {
+        let response = Response::redirect("/new home", Redirect::SeeOther);
+        assert_eq!(response.status(), &Status::SeeOther);
+        assert_eq!(response.header("Location").unwrap(), "/new%20home");
+
+        assert_eq!(Redirect::MovedPermanently.status().as_u16(), 301);
+        assert_eq!(Redirect::Found.status().as_u16(), 302);
+        assert_eq!(Redirect::Temporary.status().as_u16(), 307);
+        assert_eq!(Redirect::Permanent.status().as_u16(), 308);
+    }
+
+    #[test]
+    fn test_hostname() {
+        assert_eq!(hostname("example.com:8080"), Some("example.com"));
+        assert_eq!(hostname("[::1]:8080"), Some("[::1]"));
+        assert_eq!(hostname("evil.com/path"), None);
+        assert_eq!(hostname(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_https_redirect() {
+        let mut https = HttpsRedirect::new();
+        https.with_port(8443);
+        let mut app = build(https);
+
+        let response =
+            get(&mut app, "/potato?x=1", &[("Host", "example.com:8080")]).await;
+        assert_eq!(response.status(), &Status::PermanentRedirect);
+        assert_eq!(
+            response.header("Location").unwrap(),
+            "https://example.com:8443/potato?x=1"
+        );
+
+        let response =
+            get(&mut app, "/potato", &[("X-Forwarded-Proto", "https")]).await;
+        assert_eq!(response.status(), &Status::OK);
+
+        let forwarded = "for=192.0.2.60;proto=\"https\";by=203.0.113.43";
+        let response =
+            get(&mut app, "/potato", &[("Forwarded", forwarded)]).await;
+        assert_eq!(response.status(), &Status::OK);
+
+        let response = get(&mut app, "/potato", &[]).await;
+        assert_eq!(response.status(), &Status::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_redirect() {
+        let mut app = build(TrailingSlashRedirect::trim());
+
+        let response = get(&mut app, "/potato/?x=1", &[]).await;
+        assert_eq!(response.status(), &Status::PermanentRedirect);
+        assert_eq!(response.header("Location").unwrap(), "/potato?x=1");
+
+        let response = get(&mut app, "/", &[]).await;
+        assert_eq!(response.status(), &Status::OK);
+
+        let mut app = build(TrailingSlashRedirect::append());
+
+        let response = get(&mut app, "/potato", &[]).await;
+        assert_eq!(response.header("Location").unwrap(), "/potato/");
+
+        let response = get(&mut app, "/style.css", &[]).await;
+        assert_eq!(response.status(), &Status::OK);
+    }
+
+    #[tokio::test]
+    async fn test_no_redirect_off_site() {
+        let mut app = App::new(Router::new());
+        app.with_middleware(TrailingSlashRedirect::trim());
+
+        let response = get(&mut app, "//evil.com/", &[]).await;
+        assert_eq!(response.header("Location").unwrap(), "/evil.com");
+    }
+}
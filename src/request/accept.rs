@@ -0,0 +1,181 @@
+// One entry of an `Accept`-style header, like `text/html;q=0.8`. Other
+// parameters are dropped, only the value and its weight are kept:
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preference {
+    value: String,
+    q: f32,
+}
+
+impl Preference {
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+}
+
+// The entries of an `Accept`, `Accept-Language`, `Accept-Charset` or
+// `Accept-Encoding` header, most preferred first. Entries with equal
+// weights keep their order, entries with an invalid weight are dropped:
+pub fn parse(header: &str) -> Vec<Preference> {
+    let mut preferences = Vec::new();
+
+    for entry in header.split(',') {
+        let mut params = entry.split(';');
+        let value = params.next().unwrap_or("").trim();
+        if value.is_empty() {
+            continue;
+        }
+
+        if let Some(q) = parse_q(params) {
+            preferences.push(Preference {
+                value: value.to_owned(),
+                q,
+            });
+        }
+    }
+
+    preferences.sort_by(|a, b| b.q.total_cmp(&a.q));
+    preferences
+}
+
+// The q parameter of an entry, 1 if it has none. `None` if it isn't a
+// valid weight:
+fn parse_q<'a>(params: impl Iterator<Item = &'a str>) -> Option<f32> {
+    for param in params {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+
+        if key.eq_ignore_ascii_case("q") {
+            return match value.parse::<f32>() {
+                Ok(q) if (0.0..=1.0).contains(&q) => Some(q),
+                _ => None,
+            };
+        }
+    }
+
+    Some(1.0)
+}
+
+// How closely a preference matches an offer, `None` if it doesn't. The
+// closest match decides the offer's weight:
+pub(crate) type Specificity = fn(&str, &str) -> Option<usize>;
+
+// `*/*`, then `type/*`, then the exact media type:
+pub(crate) fn media_type(range: &str, offer: &str) -> Option<usize> {
+    if range == "*/*" {
+        return Some(0);
+    }
+
+    let (range_type, range_subtype) = range.split_once('/')?;
+    let (offer_type, _) = offer.split_once('/')?;
+
+    match range_subtype {
+        "*" if range_type.eq_ignore_ascii_case(offer_type) => Some(1),
+        _ if range.eq_ignore_ascii_case(offer) => Some(2),
+        _ => None,
+    }
+}
+
+// A language range matches the tag itself and the tags below it, so
+// `en` matches `en-GB`. Longer ranges are closer:
+pub(crate) fn language(range: &str, tag: &str) -> Option<usize> {
+    if range == "*" {
+        return Some(0);
+    }
+
+    let prefix = tag.get(..range.len())?;
+    let rest = &tag[range.len()..];
+
+    match prefix.eq_ignore_ascii_case(range) {
+        true if rest.is_empty() || rest.starts_with('-') => Some(range.len()),
+        _ => None,
+    }
+}
+
+pub(crate) fn exact(value: &str, offer: &str) -> Option<usize> {
+    match value {
+        "*" => Some(0),
+        value if value.eq_ignore_ascii_case(offer) => Some(1),
+        _ => None,
+    }
+}
+
+// The offer with the highest weight, the first one of those on ties.
+// Without a header, anything is acceptable. With no offers, nothing is:
+pub(crate) fn best<'a>(
+    header: Option<&str>,
+    offers: &[&'a str],
+    specificity: Specificity,
+) -> Option<&'a str> {
+    let preferences = match header {
+        Some(header) => parse(header),
+        None => return offers.first().copied(),
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for offer in offers {
+        let q = preferences
+            .iter()
+            .filter_map(|p| specificity(&p.value, offer).map(|s| (s, p.q)))
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q);
+
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((offer, q));
+        }
+    }
+
+    best.map(|(offer, _)| offer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{best, exact, language, media_type, parse};
+
+    #[test]
+    fn test_parse() {
+        let preferences =
+            parse("text/html;level=1, */*;q=0.1, a/b;q=x, ,c/d;q=0.5");
+        let values: Vec<_> = preferences
+            .iter()
+            .map(|p| (p.value().as_str(), p.q()))
+            .collect();
+
+        assert_eq!(values, [("text/html", 1.0), ("c/d", 0.5), ("*/*", 0.1)]);
+    }
+
+    #[test]
+    fn test_best() {
+        let offers = ["application/json", "text/html"];
+        let accept = |header| best(Some(header), &offers, media_type);
+
+        assert_eq!(accept("text/html"), Some("text/html"));
+        assert_eq!(accept("text/*;q=0.9, */*;q=0.8"), Some("text/html"));
+        assert_eq!(accept("*/*"), Some("application/json"));
+        assert_eq!(
+            accept("text/html;q=0.5, application/json"),
+            Some("application/json")
+        );
+        assert_eq!(accept("*/*, text/html;q=0"), Some("application/json"));
+        assert_eq!(accept("image/png"), None);
+        assert_eq!(best(None, &offers, media_type), Some("application/json"));
+
+        let offers = ["en-GB", "fr"];
+        let accept = |header| best(Some(header), &offers, language);
+        assert_eq!(accept("fr-CA, fr;q=0.8, en;q=0.5"), Some("fr"));
+        assert_eq!(accept("en"), Some("en-GB"));
+        assert_eq!(accept("e"), None);
+
+        let offers = ["utf-8"];
+        assert_eq!(best(Some("UTF-8"), &offers, exact), Some("utf-8"));
+        assert_eq!(best(Some("*;q=0"), &offers, exact), None);
+
+        assert_eq!(best(None, &[], exact), None);
+        assert_eq!(best(Some("*"), &[], exact), None);
+    }
+}
@@ -0,0 +1,145 @@
+use futures_core::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncRead, ReadBuf};
+use tokio::sync::oneshot;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+// A request body that hasn't been read yet. It can be consumed as an
+// AsyncRead, or as a Stream of chunks. Reading straight from the socket
+// means a slow consumer slows the client down instead of filling memory:
+pub struct BodyStream {
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    on_first_read: Option<oneshot::Sender<()>>,
+}
+
+impl BodyStream {
+    pub fn new<R>(reader: R) -> Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        Self {
+            reader: Box::new(reader),
+            on_first_read: None,
+        }
+    }
+
+    // Used by the connection to send `100 Continue` once the handler
+    // starts reading:
+    pub(crate) fn on_first_read(&mut self, tx: oneshot::Sender<()>) {
+        self.on_first_read = Some(tx);
+    }
+
+    // Reads the next chunk, `None` once the body is exhausted:
+    pub async fn chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let n = tokio::io::AsyncReadExt::read(self, &mut buf).await?;
+
+        match n {
+            0 => Ok(None),
+            n => {
+                buf.truncate(n);
+                Ok(Some(buf))
+            }
+        }
+    }
+}
+
+// A body that turned out larger than we accept once it was read, like
+// a compressed body that inflates too far. Routes answer it with
+// `413 Payload Too Large`:
+#[derive(Debug, PartialEq)]
+pub struct BodyTooLarge;
+
+impl BodyTooLarge {
+    // Whether a body read failed because of it:
+    pub fn is(error: &io::Error) -> bool {
+        error.get_ref().is_some_and(|e| e.is::<BodyTooLarge>())
+    }
+}
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("body too large")
+    }
+}
+
+impl std::error::Error for BodyTooLarge {}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
+impl AsyncRead for BodyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(tx) = self.on_first_read.take() {
+            let _ = tx.send(());
+        }
+
+        Pin::new(&mut self.reader).poll_read(cx, buf)
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let mut chunk = [0; CHUNK_SIZE];
+        let mut buf = ReadBuf::new(&mut chunk);
+
+        match ready!(self.as_mut().poll_read(cx, &mut buf)) {
+            Ok(()) if buf.filled().is_empty() => Poll::Ready(None),
+            Ok(()) => Poll::Ready(Some(Ok(buf.filled().to_vec()))),
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BodyStream;
+
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_chunks() {
+        let data = vec![7u8; 20_000];
+        let mut stream = BodyStream::new(std::io::Cursor::new(data.clone()));
+
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.chunk().await.unwrap() {
+            assert!(chunk.len() <= 8 * 1024);
+            received.extend(chunk);
+        }
+
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn test_stream_and_read() {
+        let mut stream = BodyStream::new(&b"hello world"[..]);
+
+        let first = poll_fn(|cx| {
+            futures_core::Stream::poll_next(Pin::new(&mut stream), cx)
+        })
+        .await;
+        assert_eq!(first.unwrap().unwrap(), b"hello world");
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+}
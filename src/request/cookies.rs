@@ -0,0 +1,101 @@
+use super::query::percent_decode;
+use super::request::Request;
+use crate::extract::{FromRequest, Rejection};
+
+// The cookies sent in a request's `Cookie` header, in the order they
+// were sent. Values are percent-decoded and unquoted:
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Cookies {
+    pairs: Vec<(String, String)>,
+}
+
+impl Cookies {
+    pub fn parse(header: &str) -> Self {
+        let mut pairs = Vec::new();
+
+        for cookie in header.split(';') {
+            let (name, value) = match cookie.trim().split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"').unwrap_or(quoted),
+                None => value,
+            };
+
+            pairs.push((name.to_owned(), percent_decode(value)));
+        }
+
+        Self { pairs }
+    }
+
+    // When a name is sent more than once, the first value wins. Browsers
+    // send the cookie with the most specific path first:
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.pairs.iter().find(|(k, _)| k == name).map(|(_, v)| v)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.pairs.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+impl FromRequest for Cookies {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        Ok(request.cookies())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cookies;
+    use crate::Request;
+
+    #[test]
+    fn test_parse() {
+        let cookies =
+            Cookies::parse("darkmode=true; token=\"a%20b\";bad; =x; lang=en");
+
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies.get("darkmode"), Some(&"true".to_owned()));
+        assert_eq!(cookies.get("token"), Some(&"a b".to_owned()));
+        assert_eq!(cookies.get("lang"), Some(&"en".to_owned()));
+        assert!(!cookies.contains("bad"));
+    }
+
+    #[test]
+    fn test_first_value_wins() {
+        let cookies = Cookies::parse("id=specific; id=general");
+
+        assert_eq!(cookies.get("id"), Some(&"specific".to_owned()));
+        assert_eq!(cookies.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut request = Request::default();
+        request.with_header("cookie", "session=abc123");
+
+        let cookies = request.extract::<Cookies>().unwrap();
+        assert_eq!(cookies.get("session"), Some(&"abc123".to_owned()));
+
+        assert!(Request::default().cookies().is_empty());
+    }
+}
@@ -0,0 +1,529 @@
+use super::query::QueryValue;
+
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, IntoDeserializer,
+    MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+pub(crate) fn from_value<T>(value: QueryValue) -> Result<T, Error>
+where
+    T: de::DeserializeOwned,
+{
+    T::deserialize(ValueDeserializer { value, key: None })
+}
+
+// Deserializes a parsed query (or form) tree. Every leaf is a string, so
+// numbers and booleans are parsed on demand and reported against the key
+// they came from:
+struct ValueDeserializer {
+    value: QueryValue,
+    key: Option<String>,
+}
+
+impl ValueDeserializer {
+    fn new(value: QueryValue, key: Option<String>) -> Self {
+        Self { value, key }
+    }
+
+    fn leaf(&self, expected: &str) -> Result<&str, Error> {
+        match &self.value {
+            QueryValue::String(s) => Ok(s),
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    fn error(&self, expected: &str) -> Error {
+        let found = match &self.value {
+            QueryValue::String(s) => format!("`{}`", s),
+            QueryValue::Seq(_) => "a sequence".to_owned(),
+            QueryValue::Map(_) => "a map".to_owned(),
+        };
+
+        match &self.key {
+            Some(key) => Error(format!(
+                "invalid value {} for `{}`: expected {}",
+                found, key, expected
+            )),
+            None => {
+                Error(format!("invalid value {}: expected {}", found, expected))
+            }
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, Error> {
+        self.leaf(expected)?
+            .parse()
+            .map_err(|_| self.error(expected))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $ty:ty, $expected:literal;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(
+                self,
+                visitor: V,
+            ) -> Result<V::Value, Error> {
+                visitor.$visit(self.parse::<$ty>($expected)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            QueryValue::String(s) => visitor.visit_string(s),
+            QueryValue::Seq(items) => {
+                visitor.visit_seq(Seq::new(items, self.key))
+            }
+            QueryValue::Map(entries) => {
+                visitor.visit_map(Map::new(entries, self.key))
+            }
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8: i8, "an integer";
+        deserialize_i16 => visit_i16: i16, "an integer";
+        deserialize_i32 => visit_i32: i32, "an integer";
+        deserialize_i64 => visit_i64: i64, "an integer";
+        deserialize_u8 => visit_u8: u8, "an unsigned integer";
+        deserialize_u16 => visit_u16: u16, "an unsigned integer";
+        deserialize_u32 => visit_u32: u32, "an unsigned integer";
+        deserialize_u64 => visit_u64: u64, "an unsigned integer";
+        deserialize_f32 => visit_f32: f32, "a number";
+        deserialize_f64 => visit_f64: f64, "a number";
+        deserialize_char => visit_char: char, "a single character";
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // Checkboxes are sent as `on` when ticked:
+        match self.leaf("a boolean")? {
+            "true" | "1" | "on" => visitor.visit_bool(true),
+            "false" | "0" | "off" => visitor.visit_bool(false),
+            _ => Err(self.error("a boolean")),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match &self.value {
+            QueryValue::String(s) if s.is_empty() => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        // A single `tag=a` is a sequence of one:
+        let items = match self.value {
+            QueryValue::Seq(items) => items,
+            value @ QueryValue::String(_) => vec![value],
+            QueryValue::Map(_) => return Err(self.error("a sequence")),
+        };
+
+        visitor.visit_seq(Seq::new(items, self.key))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            QueryValue::Map(entries) => {
+                visitor.visit_map(Map::new(entries, self.key))
+            }
+            _ => Err(self.error("a map")),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let variant = self.leaf("a variant name")?.to_owned();
+        visitor.visit_enum(UnitVariant(variant))
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            QueryValue::String(s) => visitor.visit_string(s),
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.value {
+            QueryValue::String(s) => visitor.visit_byte_buf(s.into_bytes()),
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+struct Seq {
+    items: std::vec::IntoIter<QueryValue>,
+    key: Option<String>,
+    index: usize,
+}
+
+impl Seq {
+    fn new(items: Vec<QueryValue>, key: Option<String>) -> Self {
+        Self {
+            items: items.into_iter(),
+            key,
+            index: 0,
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for Seq {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        let value = match self.items.next() {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let key = self.key.as_ref().map(|k| format!("{}[{}]", k, self.index));
+        self.index += 1;
+
+        seed.deserialize(ValueDeserializer::new(value, key))
+            .map(Some)
+    }
+}
+
+struct Map {
+    entries: std::vec::IntoIter<(String, QueryValue)>,
+    key: Option<String>,
+    value: Option<(String, QueryValue)>,
+}
+
+impl Map {
+    fn new(entries: Vec<(String, QueryValue)>, key: Option<String>) -> Self {
+        Self {
+            entries: entries.into_iter(),
+            key,
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for Map {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        let (key, value) = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let deserializer: de::value::StringDeserializer<Error> =
+            key.clone().into_deserializer();
+        self.value = Some((key, value));
+
+        seed.deserialize(deserializer).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Error> {
+        let (key, value) = match self.value.take() {
+            Some(entry) => entry,
+            None => return Err(de::Error::custom("value is missing a key")),
+        };
+
+        let key = match &self.key {
+            Some(parent) => format!("{}[{}]", parent, key),
+            None => key,
+        };
+
+        seed.deserialize(ValueDeserializer::new(value, Some(key)))
+    }
+}
+
+struct UnitVariant(String);
+
+impl<'de> EnumAccess<'de> for UnitVariant {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), Error> {
+        let deserializer: de::value::StrDeserializer<Error> =
+            self.0.as_str().into_deserializer();
+        let variant = seed.deserialize(deserializer)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for UnitVariant {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        _seed: T,
+    ) -> Result<T::Value, Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Error> {
+        Err(de::Error::custom("only unit variants are supported"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::query::Query;
+    use super::from_value;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Filter {
+        min: u32,
+        max: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: u32,
+        ratio: f64,
+        exact: bool,
+        limit: Option<u8>,
+        tags: Vec<String>,
+        order: Order,
+        filter: Filter,
+    }
+
+    fn parse<T: serde::de::DeserializeOwned>(
+        raw: &str,
+    ) -> Result<T, super::Error> {
+        from_value(Query::parse(raw).nested())
+    }
+
+    #[test]
+    fn test_deserialize_struct() {
+        let search: Search = parse(
+            "q=sweet+potato&page=2&ratio=0.5&exact=on&tags=a&tags=b\
+             &order=desc&filter[min]=1&filter[max]=",
+        )
+        .unwrap();
+
+        assert_eq!(
+            search,
+            Search {
+                q: "sweet potato".to_owned(),
+                page: 2,
+                ratio: 0.5,
+                exact: true,
+                limit: None,
+                tags: vec!["a".to_owned(), "b".to_owned()],
+                order: Order::Desc,
+                filter: Filter { min: 1, max: None },
+            }
+        );
+    }
+
+    #[test]
+    fn test_single_value_sequence() {
+        #[derive(Deserialize)]
+        struct Tags {
+            tag: Vec<u32>,
+        }
+
+        let tags: Tags = parse("tag=7").unwrap();
+        assert_eq!(tags.tag, vec![7]);
+
+        let tags: Tags = parse("tag[]=1&tag[]=2").unwrap();
+        assert_eq!(tags.tag, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse::<Filter>("min=abc").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `abc` for `min`: expected an unsigned integer"
+        );
+
+        let err = parse::<Filter>("max=1").unwrap_err();
+        assert_eq!(err.to_string(), "missing field `min`");
+    }
+
+    #[test]
+    fn test_nested_errors() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Nested {
+            filter: Filter,
+            pages: Vec<Filter>,
+        }
+
+        let err = parse::<Nested>("filter[min]=abc").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `abc` for `filter[min]`: \
+             expected an unsigned integer"
+        );
+
+        let err = parse::<Nested>(
+            "filter[min]=1&pages[0][min]=1&pages[1][min]=2&pages[1][max]=x",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value `x` for `pages[1][max]`: \
+             expected an unsigned integer"
+        );
+    }
+}
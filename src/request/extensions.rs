@@ -0,0 +1,66 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+// Typed values attached to a request, so middleware can hand state to
+// handlers. There is at most one value of each type:
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the value of the same type that was replaced, if any:
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Extensions;
+
+    #[test]
+    fn test_typed_values() {
+        let mut extensions = Extensions::new();
+
+        assert_eq!(extensions.insert(5u32), None);
+        assert_eq!(extensions.insert(7u32), Some(5));
+        extensions.insert("user".to_owned());
+
+        *extensions.get_mut::<u32>().unwrap() += 1;
+
+        assert_eq!(extensions.get::<u32>(), Some(&8));
+        assert_eq!(extensions.get::<String>(), Some(&"user".to_owned()));
+        assert_eq!(extensions.remove::<u32>(), Some(8));
+        assert_eq!(extensions.get::<u32>(), None);
+    }
+}
@@ -1,7 +1,21 @@
+pub mod accept;
+pub mod body;
+pub mod cookies;
+pub mod de;
+pub mod extensions;
 pub mod method;
+pub mod multipart;
 pub mod path_and_query;
+pub mod query;
+#[allow(clippy::module_inception)]
 pub mod request;
 pub mod start_line;
 
+pub use accept::Preference;
+pub use body::{BodyStream, BodyTooLarge};
+pub use cookies::Cookies;
+pub use extensions::Extensions;
 pub use method::Method;
+pub use multipart::{Multipart, MultipartLimits, MultipartStream, Part};
+pub use query::{Query, QueryValue};
 pub use request::Request;
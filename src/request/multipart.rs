@@ -0,0 +1,954 @@
+use super::body::BodyStream;
+use super::query::percent_decode;
+use crate::extract::Rejection;
+use crate::response::Status;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+const MAX_HEADERS_SIZE: usize = 8 * 1024;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub enum MultipartError {
+    NotMultipart,
+    Malformed(&'static str),
+    PartTooLarge,
+    BodyTooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::NotMultipart => f.write_str(
+                "Expected request with `Content-Type: multipart/form-data`",
+            ),
+            MultipartError::Malformed(reason) => {
+                write!(f, "Malformed multipart body: {}", reason)
+            }
+            MultipartError::PartTooLarge => {
+                f.write_str("Multipart part exceeds the size limit")
+            }
+            MultipartError::BodyTooLarge => {
+                f.write_str("Multipart body exceeds the size limit")
+            }
+            MultipartError::Io(e) => write!(f, "Failed to store upload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> Self {
+        MultipartError::Io(e)
+    }
+}
+
+impl From<MultipartError> for Rejection {
+    fn from(e: MultipartError) -> Self {
+        let status = match e {
+            MultipartError::NotMultipart => Status::UnsupportedMediaType,
+            MultipartError::Malformed(_) => Status::BadRequest,
+            MultipartError::PartTooLarge | MultipartError::BodyTooLarge => {
+                Status::PayloadTooLarge
+            }
+            MultipartError::Io(_) => Status::Internal,
+        };
+
+        Rejection::new(status, &e.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    part_size: usize,
+    total_size: usize,
+    spill: Option<(usize, PathBuf)>,
+}
+
+impl MultipartLimits {
+    pub fn new() -> Self {
+        Self {
+            part_size: 8 * 1024 * 1024,
+            total_size: 32 * 1024 * 1024,
+            spill: None,
+        }
+    }
+
+    pub fn with_part_size(&mut self, bytes: usize) -> &mut Self {
+        self.part_size = bytes;
+        self
+    }
+
+    pub fn with_total_size(&mut self, bytes: usize) -> &mut Self {
+        self.total_size = bytes;
+        self
+    }
+
+    // File parts larger than `threshold` bytes are written to a temporary
+    // file in `dir` instead of being held in memory. Only parts read with
+    // `Request::multipart_stream` are, a buffered body is in memory
+    // already:
+    pub fn with_spill(&mut self, threshold: usize, dir: &Path) -> &mut Self {
+        self.spill = Some((threshold, dir.to_owned()));
+        self
+    }
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+    headers: HashMap<String, String>,
+    body: PartBody,
+}
+
+#[derive(Debug)]
+pub enum PartBody {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+impl Part {
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn filename(&self) -> Option<&String> {
+        self.filename.as_ref()
+    }
+
+    pub fn content_type(&self) -> Option<&String> {
+        self.content_type.as_ref()
+    }
+
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.body {
+            PartBody::Memory(bytes) => bytes.len() as u64,
+            PartBody::File(file) => file.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn body(&self) -> &PartBody {
+        &self.body
+    }
+
+    pub fn into_body(self) -> PartBody {
+        self.body
+    }
+
+    // The in-memory body, `None` if it was spilled to disk:
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match &self.body {
+            PartBody::Memory(bytes) => Some(bytes),
+            PartBody::File(_) => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(self.bytes()?).ok()
+    }
+}
+
+// An upload spilled to disk. The file is removed when this is dropped,
+// unless it has been persisted somewhere else:
+#[derive(Debug)]
+pub struct TempFile {
+    path: PathBuf,
+    len: u64,
+    persisted: bool,
+}
+
+impl TempFile {
+    async fn create(dir: &Path) -> io::Result<(Self, File)> {
+        loop {
+            let n = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let name = format!("potato-upload-{}-{}", std::process::id(), n);
+            let path = dir.join(name);
+
+            let mut options = OpenOptions::new();
+            match options.write(true).create_new(true).open(&path).await {
+                Ok(file) => {
+                    let temp = Self {
+                        path,
+                        len: 0,
+                        persisted: false,
+                    };
+                    return Ok((temp, file));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub async fn persist(mut self, to: &Path) -> io::Result<()> {
+        // Renaming fails across filesystems, so fall back to a copy:
+        if tokio::fs::rename(&self.path, to).await.is_err() {
+            tokio::fs::copy(&self.path, to).await?;
+            let _ = tokio::fs::remove_file(&self.path).await;
+        }
+
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    // Removed on the blocking pool when there is a runtime, so dropping
+    // a part never waits on the disk:
+    fn drop(&mut self) {
+        if self.persisted {
+            return;
+        }
+
+        let path = std::mem::take(&mut self.path);
+        let remove = move || {
+            let _ = std::fs::remove_file(path);
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Preamble,
+    Boundary,
+    Headers,
+    Body,
+    Done,
+}
+
+// Incremental parser: bytes can be fed in chunks of any size and parts
+// come out as soon as their closing boundary has been seen. Only a
+// boundary's worth of bytes is held back while searching for it, so the
+// body of the current part can be moved elsewhere as it arrives:
+pub struct MultipartParser {
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    state: State,
+    limits: MultipartLimits,
+    total: usize,
+    current: Option<Part>,
+    current_len: u64,
+    ready: VecDeque<Part>,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: &MultipartLimits) -> Self {
+        Self {
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            // The first boundary isn't preceded by a line break, pretend
+            // that it is so every delimiter looks the same:
+            buf: b"\r\n".to_vec(),
+            state: State::Preamble,
+            limits: limits.clone(),
+            total: 0,
+            current: None,
+            current_len: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), MultipartError> {
+        self.total += chunk.len();
+        if self.total > self.limits.total_size {
+            Err(MultipartError::BodyTooLarge)?
+        }
+
+        self.buf.extend_from_slice(chunk);
+        while self.step()? {}
+
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), MultipartError> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => Err(MultipartError::Malformed("unexpected end of body")),
+        }
+    }
+
+    pub fn next_part(&mut self) -> Option<Part> {
+        self.ready.pop_front()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Advances the state machine, returning false once more input is
+    // needed:
+    fn step(&mut self) -> Result<bool, MultipartError> {
+        match self.state {
+            State::Preamble => match find(&self.buf, &self.delimiter) {
+                Some(i) => {
+                    self.buf.drain(..i + self.delimiter.len());
+                    self.state = State::Boundary;
+                    Ok(true)
+                }
+                None => {
+                    let keep = self.delimiter.len() - 1;
+                    let discard = self.buf.len().saturating_sub(keep);
+                    self.buf.drain(..discard);
+                    Ok(false)
+                }
+            },
+            State::Boundary => {
+                if self.buf.len() < 2 {
+                    return Ok(false);
+                }
+
+                if self.buf.starts_with(b"--") {
+                    self.buf.clear();
+                    self.state = State::Done;
+                    return Ok(false);
+                }
+
+                // Whitespace is allowed between a boundary and its CRLF:
+                if self.buf[0] == b' ' || self.buf[0] == b'\t' {
+                    self.buf.remove(0);
+                    return Ok(true);
+                }
+
+                if !self.buf.starts_with(b"\r\n") {
+                    Err(MultipartError::Malformed("invalid boundary"))?
+                }
+
+                self.buf.drain(..2);
+                self.state = State::Headers;
+                Ok(true)
+            }
+            State::Headers => {
+                let end = match self.buf.starts_with(b"\r\n") {
+                    true => Some(0),
+                    false => find(&self.buf, b"\r\n\r\n"),
+                };
+
+                let end = match end {
+                    Some(end) => end,
+                    None if self.buf.len() > MAX_HEADERS_SIZE => {
+                        Err(MultipartError::Malformed("headers are too large"))?
+                    }
+                    None => return Ok(false),
+                };
+
+                let headers = parse_headers(&self.buf[..end])?;
+                let skip = if end == 0 { 2 } else { end + 4 };
+                self.buf.drain(..skip);
+
+                self.current = Some(Self::start_part(headers)?);
+                self.current_len = 0;
+                self.state = State::Body;
+                Ok(true)
+            }
+            State::Body => match find(&self.buf, &self.delimiter) {
+                Some(i) => {
+                    let data: Vec<u8> = self.buf.drain(..i).collect();
+                    self.write(&data)?;
+                    self.buf.drain(..self.delimiter.len());
+                    self.finish_part();
+                    self.state = State::Boundary;
+                    Ok(true)
+                }
+                None => {
+                    let keep = self.delimiter.len() - 1;
+                    let safe = self.buf.len().saturating_sub(keep);
+                    let data: Vec<u8> = self.buf.drain(..safe).collect();
+                    self.write(&data)?;
+                    Ok(false)
+                }
+            },
+            State::Done => {
+                self.buf.clear();
+                Ok(false)
+            }
+        }
+    }
+
+    fn start_part(
+        headers: HashMap<String, String>,
+    ) -> Result<Part, MultipartError> {
+        let disposition = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Disposition"))
+            .map(|(_, v)| parse_disposition(v));
+
+        let (name, filename) = match disposition {
+            Some((Some(name), filename)) => (name, filename),
+            _ => Err(MultipartError::Malformed("part is missing a name"))?,
+        };
+
+        let content_type = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, v)| v.to_owned());
+
+        Ok(Part {
+            name,
+            filename,
+            content_type,
+            headers,
+            body: PartBody::Memory(Vec::new()),
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), MultipartError> {
+        let part = match &mut self.current {
+            Some(part) => part,
+            None => return Ok(()),
+        };
+
+        // Counted here, the body may have been moved to disk since:
+        self.current_len += data.len() as u64;
+        if self.current_len > self.limits.part_size as u64 {
+            Err(MultipartError::PartTooLarge)?
+        }
+
+        if let PartBody::Memory(bytes) = &mut part.body {
+            bytes.extend_from_slice(data);
+        }
+
+        Ok(())
+    }
+
+    fn finish_part(&mut self) {
+        if let Some(part) = self.current.take() {
+            self.ready.push_back(part);
+        }
+    }
+}
+
+// Iterates over the parts of a buffered body:
+pub struct Multipart<'a> {
+    body: &'a [u8],
+    parser: MultipartParser,
+    finished: bool,
+}
+
+impl<'a> Multipart<'a> {
+    pub(crate) fn new(
+        body: &'a [u8],
+        boundary: &str,
+        limits: &MultipartLimits,
+    ) -> Self {
+        Self {
+            body,
+            parser: MultipartParser::new(boundary, limits),
+            finished: false,
+        }
+    }
+}
+
+impl<'a> Iterator for Multipart<'a> {
+    type Item = Result<Part, MultipartError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(part) = self.parser.next_part() {
+                return Some(Ok(part));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if self.body.is_empty() || self.parser.is_done() {
+                self.finished = true;
+                return self.parser.finish().err().map(Err);
+            }
+
+            let (chunk, rest) =
+                self.body.split_at(CHUNK_SIZE.min(self.body.len()));
+            self.body = rest;
+
+            if let Err(e) = self.parser.feed(chunk) {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+// Reads parts from an unread body as it arrives from the client. File
+// parts past the spill threshold are written to disk as they arrive:
+pub struct MultipartStream {
+    stream: BodyStream,
+    parser: MultipartParser,
+    spill: Option<(TempFile, File)>,
+    finished: bool,
+}
+
+impl MultipartStream {
+    pub(crate) fn new(
+        stream: BodyStream,
+        boundary: &str,
+        limits: &MultipartLimits,
+    ) -> Self {
+        Self {
+            stream,
+            parser: MultipartParser::new(boundary, limits),
+            spill: None,
+            finished: false,
+        }
+    }
+
+    pub async fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        loop {
+            if let Some(mut part) = self.parser.next_part() {
+                if let Err(e) = self.finish_spill(&mut part).await {
+                    self.finished = true;
+                    return Err(e);
+                }
+
+                return Ok(Some(part));
+            }
+
+            if self.finished {
+                return Ok(None);
+            }
+
+            let mut result = self.read().await;
+
+            // Parts that are done come first, they may own the file:
+            if result.is_ok() && self.parser.ready.is_empty() {
+                if let Some(mut part) = self.parser.current.take() {
+                    result = self.spill(&mut part).await;
+                    self.parser.current = Some(part);
+                }
+            }
+
+            if let Err(e) = result {
+                self.finished = true;
+                return Err(e);
+            }
+        }
+    }
+
+    // Moves the in-memory body of a file part to its temporary file, once
+    // the part is being spilled or has grown past the threshold:
+    async fn spill(&mut self, part: &mut Part) -> Result<(), MultipartError> {
+        let (threshold, dir) = match &self.parser.limits.spill {
+            Some(spill) => spill,
+            None => return Ok(()),
+        };
+
+        let bytes = match &mut part.body {
+            PartBody::Memory(bytes)
+                if part.filename.is_some()
+                    && (self.spill.is_some() || bytes.len() > *threshold) =>
+            {
+                std::mem::take(bytes)
+            }
+            _ => return Ok(()),
+        };
+
+        if self.spill.is_none() {
+            self.spill = Some(TempFile::create(dir).await?);
+        }
+
+        if let Some((temp, file)) = &mut self.spill {
+            file.write_all(&bytes).await?;
+            temp.len += bytes.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    // The rest of a spilled part goes after what was written, then the
+    // part gets the file:
+    async fn finish_spill(
+        &mut self,
+        part: &mut Part,
+    ) -> Result<(), MultipartError> {
+        self.spill(part).await?;
+
+        if let Some((temp, mut file)) = self.spill.take() {
+            file.flush().await?;
+            part.body = PartBody::File(temp);
+        }
+
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<(), MultipartError> {
+        if self.parser.is_done() {
+            self.finished = true;
+            return Ok(());
+        }
+
+        match self.stream.chunk().await? {
+            Some(chunk) => self.parser.feed(&chunk),
+            None => {
+                self.finished = true;
+                self.parser.finish()
+            }
+        }
+    }
+}
+
+// Extracts the boundary from a `multipart/form-data` Content-Type:
+pub(crate) fn boundary(content_type: &str) -> Option<String> {
+    let (media_type, params) = content_type.split_once(';')?;
+
+    if !media_type
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    parse_params(params)
+        .into_iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v)
+        .filter(|v| !v.is_empty() && v.len() <= 70)
+}
+
+fn parse_headers(
+    raw: &[u8],
+) -> Result<HashMap<String, String>, MultipartError> {
+    let raw = std::str::from_utf8(raw)
+        .map_err(|_| MultipartError::Malformed("headers are not UTF-8"))?;
+
+    let mut headers = HashMap::new();
+
+    for line in raw.split("\r\n").filter(|l| !l.is_empty()) {
+        let (key, value) = match line.split_once(':') {
+            Some(kv) => kv,
+            None => Err(MultipartError::Malformed("invalid header"))?,
+        };
+
+        headers.insert(key.trim().to_owned(), value.trim().to_owned());
+    }
+
+    Ok(headers)
+}
+
+// Reads the name and filename out of `form-data; name="a"; filename="b"`.
+// An RFC 5987 `filename*` takes precedence over `filename`:
+fn parse_disposition(value: &str) -> (Option<String>, Option<String>) {
+    let params = match value.split_once(';') {
+        Some((_, params)) => parse_params(params),
+        None => return (None, None),
+    };
+
+    let get = |name: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.to_owned())
+    };
+
+    let extended = get("filename*").and_then(|v| {
+        let (charset, rest) = v.split_once('\'')?;
+        let (_, encoded) = rest.split_once('\'')?;
+        match charset.eq_ignore_ascii_case("utf-8") {
+            true => Some(percent_decode(encoded)),
+            false => None,
+        }
+    });
+
+    (get("name"), extended.or_else(|| get("filename")))
+}
+
+// Splits `; a=1; b="quoted; value"` into key/value pairs:
+fn parse_params(input: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while matches!(chars.peek(), Some(c) if *c == ';' || c.is_whitespace())
+        {
+            chars.next();
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            key.push(c);
+        }
+
+        if key.is_empty() {
+            break;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => value.extend(chars.next()),
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| *c != ';') {
+                    value.push(c);
+                }
+            }
+        }
+
+        params.push((key.trim().to_owned(), value.trim().to_owned()));
+    }
+
+    params
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        boundary, BodyStream, MultipartError, MultipartLimits, MultipartParser,
+        Part, PartBody,
+    };
+    use crate::{Method, Request};
+
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    const BODY: &str = "preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Sweet potato\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"photo\"; filename=\"po\\\"ta.png\"\r\n\
+Content-Type: image/png\r\n\
+\r\n\
+\x00\x01binary\r\ndata\r\n\
+--XyZ--\r\n\
+epilogue";
+
+    fn request(body: &[u8]) -> Request {
+        let mut request = Request::default();
+        request
+            .with_start_line(Method::POST, "/upload", "HTTP/1.1")
+            .with_header("Content-Type", "multipart/form-data; boundary=XyZ")
+            .with_body(body);
+
+        request
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"a b\""),
+            Some("a b".to_owned())
+        );
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[test]
+    fn test_parts() {
+        let request = request(BODY.as_bytes());
+        let parts: Vec<_> = request
+            .multipart(&MultipartLimits::default())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].name(), "title");
+        assert!(!parts[0].is_file());
+        assert_eq!(parts[0].text(), Some("Sweet potato"));
+
+        assert_eq!(parts[1].name(), "photo");
+        assert_eq!(parts[1].filename(), Some(&"po\"ta.png".to_owned()));
+        assert_eq!(parts[1].content_type(), Some(&"image/png".to_owned()));
+        assert_eq!(parts[1].bytes(), Some(&b"\x00\x01binary\r\ndata"[..]));
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let mut parser =
+            MultipartParser::new("XyZ", &MultipartLimits::default());
+
+        for byte in BODY.as_bytes() {
+            parser.feed(&[*byte]).unwrap();
+        }
+        parser.finish().unwrap();
+
+        assert_eq!(parser.next_part().unwrap().text(), Some("Sweet potato"));
+        assert_eq!(parser.next_part().unwrap().len(), 14);
+        assert!(parser.next_part().is_none());
+    }
+
+    #[test]
+    fn test_limits() {
+        let request = request(BODY.as_bytes());
+
+        let err = request
+            .multipart(MultipartLimits::default().with_part_size(13))
+            .unwrap()
+            .find_map(|part| part.err())
+            .unwrap();
+        assert!(matches!(err, MultipartError::PartTooLarge));
+
+        let err = request
+            .multipart(MultipartLimits::default().with_total_size(64))
+            .unwrap()
+            .find_map(|part| part.err())
+            .unwrap();
+        assert!(matches!(err, MultipartError::BodyTooLarge));
+    }
+
+    // The photo of `BODY`, streamed a few bytes at a time so that it
+    // spills before it ends:
+    async fn spilled_photo(dir: &Path) -> Part {
+        let mut streamed = request(b"");
+
+        let (mut client, body) = tokio::io::duplex(5);
+        tokio::spawn(async move { client.write_all(BODY.as_bytes()).await });
+        streamed.with_body_stream(BodyStream::new(body));
+
+        let mut parts = streamed
+            .multipart_stream(MultipartLimits::default().with_spill(4, dir))
+            .unwrap();
+
+        // Plain fields always stay in memory:
+        let title = parts.next_part().await.unwrap().unwrap();
+        assert!(title.bytes().is_some());
+
+        parts.next_part().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_spill_to_disk() {
+        let dir = std::env::temp_dir();
+
+        let photo = spilled_photo(&dir).await;
+        assert_eq!(photo.len(), 14);
+        let path = match photo.body() {
+            PartBody::File(file) => file.path().to_owned(),
+            PartBody::Memory(_) => panic!("photo was not spilled"),
+        };
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"\x00\x01binary\r\ndata");
+
+        // The file is removed on the blocking pool:
+        drop(photo);
+        for _ in 0..100 {
+            if !path.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!path.exists());
+
+        // A buffered body is in memory already:
+        let photo = request(BODY.as_bytes())
+            .multipart(MultipartLimits::default().with_spill(4, &dir))
+            .unwrap()
+            .nth(1)
+            .unwrap()
+            .unwrap();
+        assert!(photo.bytes().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_persist() {
+        let dir = std::env::temp_dir();
+        let to = dir.join(format!("potato-persist-{}", std::process::id()));
+
+        let file = match spilled_photo(&dir).await.into_body() {
+            PartBody::File(file) => file,
+            PartBody::Memory(_) => panic!("photo was not spilled"),
+        };
+        let path = file.path().to_owned();
+
+        file.persist(&to).await.unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"\x00\x01binary\r\ndata");
+
+        std::fs::remove_file(&to).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let mut request = request(b"");
+        request.with_body_stream(BodyStream::new(BODY.as_bytes()));
+
+        let mut parts = request
+            .multipart_stream(&MultipartLimits::default())
+            .unwrap();
+
+        let title = parts.next_part().await.unwrap().unwrap();
+        assert_eq!(title.text(), Some("Sweet potato"));
+
+        let photo = parts.next_part().await.unwrap().unwrap();
+        assert_eq!(photo.filename(), Some(&"po\"ta.png".to_owned()));
+
+        assert!(parts.next_part().await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let unnamed = request(b"--XyZ\r\nContent-Type: text/plain\r\n\r\nhi");
+
+        let err = unnamed
+            .multipart(&MultipartLimits::default())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert!(matches!(err, MultipartError::Malformed(_)));
+
+        let mut plain = request(b"");
+        plain.with_header("Content-Type", "text/plain");
+        assert!(matches!(
+            plain.multipart(&MultipartLimits::default()),
+            Err(MultipartError::NotMultipart)
+        ));
+    }
+}
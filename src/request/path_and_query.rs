@@ -1,32 +1,21 @@
-use std::collections::HashMap;
+use super::query::Query;
 
 #[derive(Debug)]
 pub(super) struct PathAndQuery {
     path: String,
-    query: HashMap<String, String>,
+    query: Query,
 }
 
 impl PathAndQuery {
     pub fn from_target(target: &str) -> Self {
-        let mut query = HashMap::new();
-
-        let (path, raw_query) = match target.split_once("?") {
+        let (path, raw_query) = match target.split_once('?') {
             Some(params) => params,
             None => (target, ""),
         };
 
-        for q in raw_query.rsplit("&") {
-            let (key, value) = match q.split_once("=") {
-                Some(kv) => kv,
-                None => continue,
-            };
-
-            query.insert(key.into(), value.into());
-        }
-
         Self {
             path: path.to_owned(),
-            query,
+            query: Query::parse(raw_query),
         }
     }
 
@@ -34,7 +23,7 @@ impl PathAndQuery {
         &self.path
     }
 
-    pub fn query(&self) -> &HashMap<String, String> {
+    pub fn query(&self) -> &Query {
         &self.query
     }
 }
@@ -43,16 +32,15 @@ impl Default for PathAndQuery {
     fn default() -> Self {
         Self {
             path: String::from("/"),
-            query: HashMap::default(),
+            query: Query::default(),
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
-
-    use super::PathAndQuery;
+    use super::super::query::QueryValue;
+    use super::{PathAndQuery, Query};
 
     #[test]
     fn test_from_target_empty() {
@@ -61,7 +49,7 @@ mod test {
         let pnq = PathAndQuery::from_target(target);
 
         assert_eq!(pnq.path, "/");
-        assert_eq!(pnq.query, HashMap::default());
+        assert_eq!(pnq.query, Query::default());
     }
 
     #[test]
@@ -71,7 +59,7 @@ mod test {
         let pnq = PathAndQuery::from_target(target);
 
         assert_eq!(pnq.path, "/path/to/resource");
-        assert_eq!(pnq.query, HashMap::default());
+        assert_eq!(pnq.query, Query::default());
     }
 
     #[test]
@@ -88,7 +76,6 @@ mod test {
     }
 
     #[test]
-    #[ignore]
     fn test_from_target_multivalue_query() {
         let target = "/path?a[0]=1&a[1]=2&a[2]=3";
 
@@ -99,5 +86,25 @@ mod test {
         assert_eq!(pnq.query.get("a[0]"), Some(&"1".to_owned()));
         assert_eq!(pnq.query.get("a[1]"), Some(&"2".to_owned()));
         assert_eq!(pnq.query.get("a[2]"), Some(&"3".to_owned()));
+
+        assert_eq!(
+            pnq.query.nested().get("a"),
+            Some(&QueryValue::Seq(vec![
+                QueryValue::String("1".to_owned()),
+                QueryValue::String("2".to_owned()),
+                QueryValue::String("3".to_owned()),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_from_target_repeated_and_encoded_query() {
+        let target = "/path?tag=a%20b&tag=c&name=sweet+potato";
+
+        let pnq = PathAndQuery::from_target(target);
+
+        assert_eq!(pnq.query.get("tag"), Some(&"a b".to_owned()));
+        assert_eq!(pnq.query.get_all("tag"), vec!["a b", "c"]);
+        assert_eq!(pnq.query.get("name"), Some(&"sweet potato".to_owned()));
     }
 }
@@ -0,0 +1,338 @@
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryValue {
+    String(String),
+    Seq(Vec<QueryValue>),
+    Map(Vec<(String, QueryValue)>),
+}
+
+impl Query {
+    pub fn parse(raw: &str) -> Self {
+        let mut pairs = Vec::new();
+
+        for q in raw.split('&') {
+            let (key, value) = match q.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+
+            pairs.push((decode(key), decode(value)));
+        }
+
+        Self { pairs }
+    }
+
+    // Returns the first value sent for `key`:
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&String> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v)
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.pairs.iter().any(|(k, _)| k == key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.pairs.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Folds bracketed keys into a tree, so `a[0]=1&a[1]=2` becomes a
+    // sequence and `user[name]=bob` becomes a map. Repeated plain keys
+    // are collected into a sequence as well:
+    pub fn nested(&self) -> QueryValue {
+        let mut root = Node::Map(Vec::new());
+
+        for (key, value) in &self.pairs {
+            root.insert(&split_key(key), value.to_owned());
+        }
+
+        root.into_value()
+    }
+}
+
+impl QueryValue {
+    pub fn get(&self, key: &str) -> Option<&QueryValue> {
+        match self {
+            QueryValue::Map(entries) => {
+                entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            QueryValue::Seq(items) => items.get(key.parse::<usize>().ok()?),
+            QueryValue::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            QueryValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_seq(&self) -> Option<&Vec<QueryValue>> {
+        match self {
+            QueryValue::Seq(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// Intermediate tree used while folding keys. Map entries keep the raw
+// segment so that `[]` and numeric indices can be told apart later:
+enum Node {
+    Leaf(Vec<String>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn insert(&mut self, path: &[&str], value: String) {
+        let entries = match self {
+            Node::Map(entries) => entries,
+            // A key was used both as a plain value and as a container,
+            // e.g. `a=1&a[b]=2`. The plain value loses:
+            Node::Leaf(_) => {
+                *self = Node::Map(Vec::new());
+                return self.insert(path, value);
+            }
+        };
+
+        let (segment, rest) = match path.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        // `[]` always appends a new entry:
+        let existing = match segment.is_empty() {
+            true => None,
+            false => entries.iter_mut().find(|(k, _)| k == segment),
+        };
+
+        match (existing, rest.is_empty()) {
+            (Some((_, Node::Leaf(values))), true) => values.push(value),
+            (Some((_, node)), true) => *node = Node::Leaf(vec![value]),
+            (Some((_, node)), false) => node.insert(rest, value),
+            (None, true) => {
+                entries.push((segment.to_string(), Node::Leaf(vec![value])))
+            }
+            (None, false) => {
+                let mut node = Node::Map(Vec::new());
+                node.insert(rest, value);
+                entries.push((segment.to_string(), node));
+            }
+        }
+    }
+
+    fn into_value(self) -> QueryValue {
+        match self {
+            Node::Leaf(mut values) if values.len() == 1 => {
+                QueryValue::String(values.remove(0))
+            }
+            Node::Leaf(values) => QueryValue::Seq(
+                values.into_iter().map(QueryValue::String).collect(),
+            ),
+            Node::Map(entries) => {
+                let is_seq = !entries.is_empty()
+                    && entries.iter().all(|(k, _)| {
+                        k.is_empty() || k.bytes().all(|b| b.is_ascii_digit())
+                    });
+
+                if !is_seq {
+                    return QueryValue::Map(
+                        entries
+                            .into_iter()
+                            .map(|(k, v)| (k, v.into_value()))
+                            .collect(),
+                    );
+                }
+
+                // Explicit indices are ordered numerically among the
+                // places they take, `[]` entries stay where they were sent:
+                let mut indexed = Vec::new();
+                let mut slots = Vec::new();
+                for (key, node) in entries {
+                    match key.is_empty() {
+                        true => slots.push(Some(node)),
+                        false => {
+                            slots.push(None);
+                            indexed.push((key, node));
+                        }
+                    }
+                }
+
+                indexed.sort_by_key(|(k, _)| k.parse::<usize>().ok());
+                let mut indexed = indexed.into_iter().map(|(_, node)| node);
+
+                QueryValue::Seq(
+                    slots
+                        .into_iter()
+                        .filter_map(|slot| slot.or_else(|| indexed.next()))
+                        .map(Node::into_value)
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+// Splits `a[b][0]` into `["a", "b", "0"]`. Keys with unbalanced brackets
+// are treated as plain keys:
+fn split_key(key: &str) -> Vec<&str> {
+    let (head, mut rest) = match key.find('[') {
+        Some(0) | None => return vec![key],
+        Some(i) => (&key[..i], &key[i..]),
+    };
+
+    let mut segments = vec![head];
+
+    while !rest.is_empty() {
+        let close = match rest.starts_with('[') {
+            true => rest.find(']'),
+            false => None,
+        };
+
+        match close {
+            Some(i) => {
+                segments.push(&rest[1..i]);
+                rest = &rest[i + 1..];
+            }
+            None => return vec![key],
+        }
+    }
+
+    segments
+}
+
+// Decodes `application/x-www-form-urlencoded` text, where `+` is a space:
+pub(crate) fn decode(input: &str) -> String {
+    percent_decode(&input.replace('+', " "))
+}
+
+// Decodes `%XX` escapes. Malformed escapes are kept as they are:
+pub(crate) fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if is_hex_pair(&bytes[i + 1..]) => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 2;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+pub(crate) fn is_hex_pair(bytes: &[u8]) -> bool {
+    bytes.len() >= 2
+        && bytes[0].is_ascii_hexdigit()
+        && bytes[1].is_ascii_hexdigit()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, Query, QueryValue};
+
+    fn string(s: &str) -> QueryValue {
+        QueryValue::String(s.to_owned())
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("a+b%20c"), "a b c");
+        assert_eq!(decode("caf%C3%A9"), "café");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz"), "%zz");
+        assert_eq!(decode("1+1%2B1"), "1 1+1");
+    }
+
+    #[test]
+    fn test_multivalue() {
+        let query = Query::parse("tag=a&tag=b&q=potato");
+
+        assert_eq!(query.len(), 3);
+        assert_eq!(query.get("tag"), Some(&"a".to_owned()));
+        assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+        assert!(query.get_all("missing").is_empty());
+
+        let keys: Vec<&String> = query.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec!["tag", "tag", "q"]);
+
+        assert_eq!(
+            query.nested().get("tag"),
+            Some(&QueryValue::Seq(vec![string("a"), string("b")]))
+        );
+    }
+
+    #[test]
+    fn test_nested() {
+        let query = Query::parse(
+            "ids[]=3&ids[]=1&user[name]=bob&user[roles][1]=b&user[roles][0]=a",
+        );
+
+        let nested = query.nested();
+
+        assert_eq!(
+            nested.get("ids"),
+            Some(&QueryValue::Seq(vec![string("3"), string("1")]))
+        );
+
+        let user = nested.get("user").unwrap();
+        assert_eq!(user.get("name"), Some(&string("bob")));
+        assert_eq!(
+            user.get("roles"),
+            Some(&QueryValue::Seq(vec![string("a"), string("b")]))
+        );
+    }
+
+    #[test]
+    fn test_mixed_indices() {
+        let query = Query::parse("a[]=x&a[1]=c&a[]=y&a[0]=b");
+
+        assert_eq!(
+            query.nested().get("a"),
+            Some(&QueryValue::Seq(vec![
+                string("x"),
+                string("b"),
+                string("y"),
+                string("c"),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_brackets() {
+        let query = Query::parse("a[b=1&c]=2");
+
+        assert_eq!(
+            query.nested(),
+            QueryValue::Map(vec![
+                ("a[b".to_owned(), string("1")),
+                ("c]".to_owned(), string("2")),
+            ])
+        );
+    }
+}
@@ -1,8 +1,34 @@
 use super::{
-    method::Method, path_and_query::PathAndQuery, start_line::StartLine,
+    accept::{self, Preference, Specificity},
+    body::{BodyStream, BodyTooLarge},
+    cookies::Cookies,
+    de,
+    extensions::Extensions,
+    method::Method,
+    multipart::{
+        self, Multipart, MultipartError, MultipartLimits, MultipartStream,
+    },
+    path_and_query::PathAndQuery,
+    query::Query,
+    start_line::StartLine,
 };
+use crate::conditional::Conditions;
+use crate::cookie_jar::{CookieJar, CookieKeys};
+use crate::extract::{FromRequest, Rejection};
+use crate::response::Status;
+use crate::router::MountPath;
+
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
 use std::collections::HashMap;
-use tokio::io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use std::sync::Arc;
+use tokio::io::{
+    self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader,
+};
+
+// The most bytes of a body read into memory, unless a `BodyLimit` says
+// otherwise:
+const DEFAULT_BODY_LIMIT: usize = 16 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -12,17 +38,22 @@ pub enum ParseError {
     NoVersion,
     InvalidMethod,
     InvalidContentLength,
+    BodyTooLarge,
     UnexpectedEof,
     ReadError,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Request {
     start_line: StartLine,
     path_and_query: PathAndQuery,
     headers: HashMap<String, String>,
     route_key: Option<String>,
     content: Option<String>,
+    bytes: Option<Vec<u8>>,
+    stream: Option<BodyStream>,
+    body_limit: Option<usize>,
+    extensions: Extensions,
 }
 
 impl Request {
@@ -30,50 +61,58 @@ impl Request {
     where
         R: AsyncRead + Unpin,
     {
-        let mut lines = BufReader::new(r).lines();
+        let mut reader = BufReader::new(r);
+        let mut request = Self::from_head(&mut reader).await?;
+
+        // If a Content-Length header has been sent, read the content. The
+        // buffer grows as it arrives, the client could be lying:
+        let length = request.content_length()?;
+        if length > DEFAULT_BODY_LIMIT {
+            Err(ParseError::BodyTooLarge)?
+        }
+
+        let mut buf = Vec::new();
+        (&mut reader)
+            .take(length as u64)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(|_| ParseError::ReadError)?;
+
+        if buf.len() < length {
+            Err(ParseError::ReadError)?
+        }
+
+        (request.content, request.bytes) = Self::split_body(buf);
+
+        Ok(request)
+    }
+
+    // Parses the start line and headers, leaving the body in `reader`:
+    pub(crate) async fn from_head<R>(reader: &mut R) -> Result<Self, ParseError>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        let mut lines = reader.lines();
 
         // The start line holds the method, path, params, and http_version:
-        let start_line = match lines.next_line().await.unwrap() {
-            Some(l) => l,
-            None => Err(ParseError::NoStartLine)?,
+        let start_line = match lines.next_line().await {
+            Ok(Some(l)) => l,
+            Ok(None) => Err(ParseError::NoStartLine)?,
+            Err(_) => Err(ParseError::ReadError)?,
         };
 
         let start_line = StartLine::from_line(&start_line)?;
 
         // Parse headers into a map:
-        let headers = Self::parse_headers(&mut lines).await;
-
-        // If a Content-Length header has been sent, read the content:
-        let mut data = String::new();
-        if let Some(length) = headers.get("Content-Length") {
-            let length: usize = length
-                .parse()
-                .map_err(|_| ParseError::InvalidContentLength)?;
-            let mut buf: Vec<u8> = vec![0; length];
-
-            let mut reader = lines.into_inner();
-            reader
-                .read_exact(&mut buf)
-                .await
-                .map_err(|_| ParseError::ReadError)?;
-
-            data = std::str::from_utf8(&buf)
-                .map(|s| s.to_owned())
-                .map_err(|_| ParseError::ReadError)?
-        };
-
-        let content = match data.len() {
-            0 => None,
-            _ => Some(data),
-        };
+        let headers = Self::parse_headers(&mut lines).await?;
 
-        let path_and_query = PathAndQuery::from_target(&start_line.target());
+        let path_and_query = PathAndQuery::from_target(start_line.target());
 
         // Construct a key that can be used to locate the handler in Router:
         let route_key = Self::construct_route_key(
-            &start_line.method(),
-            &path_and_query.path(),
-            &start_line.version(),
+            start_line.method(),
+            path_and_query.path(),
+            start_line.version(),
         );
 
         Ok(Self {
@@ -81,17 +120,39 @@ impl Request {
             path_and_query,
             headers,
             route_key,
-            content,
+            ..Default::default()
         })
     }
 
+    pub fn content_length(&self) -> Result<usize, ParseError> {
+        match self.header("Content-Length") {
+            Some(length) => length
+                .trim()
+                .parse()
+                .map_err(|_| ParseError::InvalidContentLength),
+            None => Ok(0),
+        }
+    }
+
+    // Text bodies are kept as `content`, anything that isn't valid UTF-8
+    // (such as file uploads) is kept as raw bytes:
+    fn split_body(buf: Vec<u8>) -> (Option<String>, Option<Vec<u8>>) {
+        match String::from_utf8(buf) {
+            Ok(s) if s.is_empty() => (None, None),
+            Ok(s) => (Some(s), None),
+            Err(e) => (None, Some(e.into_bytes())),
+        }
+    }
+
     async fn parse_headers(
-        lines: &mut io::Lines<BufReader<impl AsyncRead + Unpin>>,
-    ) -> HashMap<String, String> {
+        lines: &mut io::Lines<impl AsyncBufRead + Unpin>,
+    ) -> Result<HashMap<String, String>, ParseError> {
         let mut header_map: HashMap<String, String> = HashMap::new();
 
-        while let Some(line) = lines.next_line().await.unwrap() {
-            if line == "" {
+        while let Some(line) =
+            lines.next_line().await.map_err(|_| ParseError::ReadError)?
+        {
+            if line.is_empty() {
                 break;
             }
 
@@ -103,7 +164,7 @@ impl Request {
             header_map.insert(key.into(), value.into());
         }
 
-        header_map
+        Ok(header_map)
     }
 
     fn construct_route_key(
@@ -111,7 +172,7 @@ impl Request {
         path: &str,
         version: &String,
     ) -> Option<String> {
-        if path == "" {
+        if path.is_empty() {
             return None;
         };
 
@@ -129,7 +190,7 @@ impl Request {
 
         self.route_key = Self::construct_route_key(
             line.method(),
-            &pnq.path(),
+            pnq.path(),
             line.version(),
         );
         self.start_line = line;
@@ -143,8 +204,28 @@ impl Request {
         self
     }
 
+    pub fn remove_header(&mut self, name: &str) -> &mut Self {
+        self.headers.retain(|k, _| !k.eq_ignore_ascii_case(name));
+        self
+    }
+
     pub fn with_content(&mut self, content: &str) -> &mut Self {
         self.content = Some(content.to_owned());
+        self.bytes = None;
+        self
+    }
+
+    pub fn with_body(&mut self, body: &[u8]) -> &mut Self {
+        (self.content, self.bytes) = Self::split_body(body.to_vec());
+        self.stream = None;
+        self
+    }
+
+    // Leaves the body unread, for handlers that consume it incrementally:
+    pub fn with_body_stream(&mut self, stream: BodyStream) -> &mut Self {
+        self.content = None;
+        self.bytes = None;
+        self.stream = Some(stream);
         self
     }
 
@@ -156,49 +237,336 @@ impl Request {
     }
 
     pub fn start_line(&self) -> &String {
-        &self.start_line.line()
+        self.start_line.line()
     }
 
     pub fn method(&self) -> &Method {
-        &self.start_line.method()
+        self.start_line.method()
     }
 
     pub fn target(&self) -> &String {
-        &self.start_line.target()
+        self.start_line.target()
+    }
+
+    // The target without its query string:
+    pub fn path(&self) -> &String {
+        self.path_and_query.path()
+    }
+
+    // Below a mounted prefix, the rest of the path. Otherwise the whole
+    // path:
+    pub fn mount_path(&self) -> &str {
+        match self.extensions.get::<MountPath>() {
+            Some(MountPath(rest)) => rest,
+            None => self.path(),
+        }
     }
 
     pub fn version(&self) -> &String {
-        &self.start_line.version()
+        self.start_line.version()
     }
 
     pub fn headers(&self) -> &HashMap<String, String> {
         &self.headers
     }
 
-    pub fn query(&self) -> &HashMap<String, String> {
-        &self.path_and_query.query()
+    // Header names are case-insensitive, unlike the keys of `headers()`:
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    // The lowercased `type/subtype` of the Content-Type header, without
+    // any parameters:
+    pub fn media_type(&self) -> Option<String> {
+        let content_type = self.header("Content-Type")?;
+        let essence = match content_type.split_once(';') {
+            Some((essence, _)) => essence,
+            None => content_type,
+        };
+
+        Some(essence.trim().to_ascii_lowercase())
+    }
+
+    // The entries of the Accept header, most preferred first:
+    pub fn accept(&self) -> Vec<Preference> {
+        self.preferences("Accept")
+    }
+
+    pub fn accept_language(&self) -> Vec<Preference> {
+        self.preferences("Accept-Language")
+    }
+
+    pub fn accept_charset(&self) -> Vec<Preference> {
+        self.preferences("Accept-Charset")
+    }
+
+    fn preferences(&self, name: &str) -> Vec<Preference> {
+        match self.header(name) {
+            Some(header) => accept::parse(header),
+            None => Vec::new(),
+        }
+    }
+
+    // The media type the client prefers out of `offers`, the first one
+    // if it likes several as much. If it accepts none of them, the
+    // rejection is a `406 Not Acceptable`:
+    pub fn negotiate<'a>(
+        &self,
+        offers: &[&'a str],
+    ) -> Result<&'a str, Rejection> {
+        self.negotiate_by("Accept", offers, accept::media_type)
+    }
+
+    // Language ranges like `en` match the tags below them, like `en-GB`:
+    pub fn negotiate_language<'a>(
+        &self,
+        offers: &[&'a str],
+    ) -> Result<&'a str, Rejection> {
+        self.negotiate_by("Accept-Language", offers, accept::language)
+    }
+
+    pub fn negotiate_charset<'a>(
+        &self,
+        offers: &[&'a str],
+    ) -> Result<&'a str, Rejection> {
+        self.negotiate_by("Accept-Charset", offers, accept::exact)
+    }
+
+    fn negotiate_by<'a>(
+        &self,
+        name: &str,
+        offers: &[&'a str],
+        specificity: Specificity,
+    ) -> Result<&'a str, Rejection> {
+        let header = self.header(name).map(|header| header.as_str());
+
+        match accept::best(header, offers, specificity) {
+            Some(offer) => Ok(offer),
+            None => Err(Rejection::new(
+                Status::NotAcceptable,
+                &format!("Not acceptable, available: {}", offers.join(", ")),
+            )),
+        }
+    }
+
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    pub fn cookies(&self) -> Cookies {
+        match self.header("Cookie") {
+            Some(header) => Cookies::parse(header),
+            None => Cookies::default(),
+        }
+    }
+
+    // Signed and private cookies are checked against the keys set with
+    // `App::with_cookie_key`:
+    pub fn cookie_jar(&self) -> CookieJar {
+        let keys = self.extensions.get::<Arc<CookieKeys>>().cloned();
+        CookieJar::new(self.cookies(), keys)
+    }
+
+    pub fn query(&self) -> &Query {
+        self.path_and_query.query()
+    }
+
+    pub fn query_as<T: DeserializeOwned>(&self) -> Result<T, Rejection> {
+        de::from_value(self.query().nested()).map_err(|e| {
+            Rejection::new(
+                Status::BadRequest,
+                &format!("Invalid query string: {}", e),
+            )
+        })
+    }
+
+    // Parses an `application/x-www-form-urlencoded` body the same way as
+    // the query string:
+    pub fn form(&self) -> Result<Query, Rejection> {
+        let media_type = self.media_type();
+        if media_type.as_deref() != Some("application/x-www-form-urlencoded") {
+            Err(Rejection::new(
+                Status::UnsupportedMediaType,
+                "Expected request with \
+                 `Content-Type: application/x-www-form-urlencoded`",
+            ))?
+        }
+
+        match &self.content {
+            Some(content) => Ok(Query::parse(content)),
+            None => Ok(Query::default()),
+        }
+    }
+
+    pub fn multipart(
+        &self,
+        limits: &MultipartLimits,
+    ) -> Result<Multipart<'_>, MultipartError> {
+        let boundary = match self.header("Content-Type") {
+            Some(content_type) => multipart::boundary(content_type),
+            None => None,
+        };
+
+        match boundary {
+            Some(boundary) => {
+                Ok(Multipart::new(self.body(), &boundary, limits))
+            }
+            None => Err(MultipartError::NotMultipart),
+        }
+    }
+
+    pub fn multipart_stream(
+        &mut self,
+        limits: &MultipartLimits,
+    ) -> Result<MultipartStream, MultipartError> {
+        let boundary = match self.header("Content-Type") {
+            Some(content_type) => multipart::boundary(content_type),
+            None => None,
+        };
+
+        let boundary = match boundary {
+            Some(boundary) => boundary,
+            None => Err(MultipartError::NotMultipart)?,
+        };
+
+        // A buffered body can be streamed just as well:
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => BodyStream::new(std::io::Cursor::new(self.body().to_vec())),
+        };
+
+        Ok(MultipartStream::new(stream, &boundary, limits))
+    }
+
+    pub fn form_as<T: DeserializeOwned>(&self) -> Result<T, Rejection> {
+        de::from_value(self.form()?.nested()).map_err(|e| {
+            Rejection::new(
+                Status::UnprocessableEntity,
+                &format!("Invalid form body: {}", e),
+            )
+        })
+    }
+
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Rejection> {
+        let is_json = match self.media_type() {
+            Some(m) => m == "application/json" || m.ends_with("+json"),
+            None => false,
+        };
+
+        if !is_json {
+            Err(Rejection::new(
+                Status::UnsupportedMediaType,
+                "Expected request with `Content-Type: application/json`",
+            ))?
+        }
+
+        let content = match &self.content {
+            Some(content) => content.as_str(),
+            None => "",
+        };
+
+        serde_json::from_str(content).map_err(|e| {
+            let status = match e.classify() {
+                serde_json::error::Category::Data => {
+                    Status::UnprocessableEntity
+                }
+                _ => Status::BadRequest,
+            };
+
+            Rejection::new(status, &format!("Invalid JSON body: {}", e))
+        })
+    }
+
+    // Checks the request's conditional headers against the current
+    // validators of the resource, before a handler changes it. `None`
+    // means go ahead, otherwise respond with the status:
+    pub fn precondition(
+        &self,
+        etag: Option<&str>,
+        last_modified: Option<DateTime<Utc>>,
+    ) -> Option<Status> {
+        Conditions::from_request(self).evaluate(
+            self.method(),
+            etag,
+            last_modified,
+        )
+    }
+
+    pub fn extract<T: FromRequest>(&self) -> Result<T, Rejection> {
+        T::from_request(self)
     }
 
     pub fn content(&self) -> &Option<String> {
         &self.content
     }
-}
 
-impl Default for Request {
-    fn default() -> Self {
-        Self {
-            start_line: StartLine::default(),
-            path_and_query: PathAndQuery::default(),
-            headers: HashMap::default(),
-            route_key: None,
-            content: None,
+    // Takes the unread body. Only streaming routes receive one, for all
+    // other routes the body has already been read into `content()`:
+    pub fn take_body_stream(&mut self) -> Option<BodyStream> {
+        self.stream.take()
+    }
+
+    // The most bytes `buffer_body` reads into memory:
+    pub fn with_body_limit(&mut self, max: usize) -> &mut Self {
+        self.body_limit = Some(max);
+        self
+    }
+
+    // Reads an unread body into memory, so `content()` and `body()` can be
+    // used as usual. A body past the limit fails with `BodyTooLarge`:
+    pub async fn buffer_body(&mut self) -> io::Result<()> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => return Ok(()),
+        };
+
+        let limit = self.body_limit.unwrap_or(DEFAULT_BODY_LIMIT);
+
+        // A body that says up front it is too large isn't read at all:
+        if self.content_length().is_ok_and(|length| length > limit) {
+            return Err(io::Error::other(BodyTooLarge));
+        }
+
+        let mut buf = Vec::new();
+        (&mut stream)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut buf)
+            .await?;
+
+        if buf.len() > limit {
+            return Err(io::Error::other(BodyTooLarge));
+        }
+
+        (self.content, self.bytes) = Self::split_body(buf);
+
+        Ok(())
+    }
+
+    // The raw body, whether or not it is valid UTF-8:
+    pub fn body(&self) -> &[u8] {
+        match (&self.content, &self.bytes) {
+            (Some(content), _) => content.as_bytes(),
+            (None, Some(bytes)) => bytes,
+            (None, None) => &[],
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Method, Request};
+    use super::{BodyStream, BodyTooLarge, Method, ParseError, Request};
+    use crate::extract::{Form, Query};
+    use crate::Status;
+
+    use serde::Deserialize;
 
     #[tokio::test]
     async fn test_from_connection() {
@@ -231,4 +599,85 @@ mod test {
         assert!(request.content().is_some());
         assert_eq!(request.content(), &Some("Hello".to_owned()));
     }
+
+    #[tokio::test]
+    async fn test_body_limit() {
+        let raw = "POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n";
+        let result = Request::from_connection(&mut raw.as_bytes()).await;
+        assert!(matches!(result, Err(ParseError::BodyTooLarge)));
+
+        let mut request = Request::default();
+        request
+            .with_body_limit(4)
+            .with_body_stream(BodyStream::new(&b"Hello"[..]));
+        let e = request.buffer_body().await.unwrap_err();
+        assert!(BodyTooLarge::is(&e));
+
+        let mut request = Request::default();
+        request
+            .with_body_limit(5)
+            .with_body_stream(BodyStream::new(&b"Hello"[..]));
+        request.buffer_body().await.unwrap();
+        assert_eq!(request.body(), b"Hello");
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Params {
+        id: u32,
+        verbose: Option<bool>,
+    }
+
+    #[test]
+    fn test_query_as() {
+        let mut request = Request::default();
+        request.with_start_line(Method::GET, "/?id=7&verbose=true", "HTTP/1.1");
+
+        let params: Params = request.query_as().unwrap();
+        assert_eq!(params.id, 7);
+        assert_eq!(params.verbose, Some(true));
+
+        let Query(params) = request.extract::<Query<Params>>().unwrap();
+        assert_eq!(params.id, 7);
+    }
+
+    #[test]
+    fn test_form_as() {
+        let mut request = Request::default();
+        request
+            .with_start_line(Method::POST, "/", "HTTP/1.1")
+            .with_header(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=UTF-8",
+            )
+            .with_content("id=7&verbose=on&note=hello+there");
+
+        let form = request.form().unwrap();
+        assert_eq!(form.get("note"), Some(&"hello there".to_owned()));
+
+        let Form(params) = request.extract::<Form<Params>>().unwrap();
+        assert_eq!(params.id, 7);
+        assert_eq!(params.verbose, Some(true));
+
+        request.with_content("verbose=on");
+        let rejection = request.form_as::<Params>().unwrap_err();
+        assert_eq!(rejection.status(), &Status::UnprocessableEntity);
+
+        request.with_header("Content-Type", "text/plain");
+        let rejection = request.form().unwrap_err();
+        assert_eq!(rejection.status(), &Status::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn test_query_as_rejection() {
+        let mut request = Request::default();
+        request.with_start_line(Method::GET, "/?id=seven", "HTTP/1.1");
+
+        let rejection = request.query_as::<Params>().unwrap_err();
+        assert_eq!(rejection.status(), &Status::BadRequest);
+        assert_eq!(
+            rejection.message(),
+            "Invalid query string: invalid value `seven` for `id`: \
+             expected an unsigned integer"
+        );
+    }
 }
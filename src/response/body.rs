@@ -0,0 +1,357 @@
+use crate::request::BodyStream;
+
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::poll_fn;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use tokio::io::{
+    self, AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt, ReadBuf,
+};
+
+type ChunkStream = Pin<Box<dyn Stream<Item = io::Result<Vec<u8>>> + Send>>;
+
+// A response body that is produced while it is sent, rather than held
+// in memory. Without a known length it is sent chunked:
+pub(crate) struct StreamBody {
+    stream: ChunkStream,
+    pub(crate) length: Option<u64>,
+    pub(crate) trailers: Option<Trailers>,
+}
+
+impl StreamBody {
+    pub(crate) fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        Self {
+            stream: Box::pin(stream),
+            length: None,
+            trailers: None,
+        }
+    }
+
+    async fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        poll_fn(|cx| self.stream.as_mut().poll_next(cx)).await
+    }
+
+    // Copies the stream as is. A stream that doesn't match the length
+    // it was given is an error, the connection is closed after it:
+    pub(crate) async fn write<W>(
+        &mut self,
+        out: &mut W,
+        length: u64,
+    ) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut written = 0;
+
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            written += chunk.len() as u64;
+            if written > length {
+                Err(io::Error::other("body is longer than its length"))?
+            }
+
+            out.write_all(&chunk).await?;
+            out.flush().await?;
+        }
+
+        if written < length {
+            Err(io::Error::other("body is shorter than its length"))?
+        }
+
+        Ok(())
+    }
+
+    // Each chunk is sent as soon as the stream produces it, followed by
+    // the trailers once it ends:
+    pub(crate) async fn write_chunked<W>(
+        &mut self,
+        out: &mut W,
+    ) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            if chunk.is_empty() {
+                continue;
+            }
+
+            out.write_all(format!("{:X}\r\n", chunk.len()).as_bytes())
+                .await?;
+            out.write_all(&chunk).await?;
+            out.write_all(b"\r\n").await?;
+            out.flush().await?;
+        }
+
+        out.write_all(b"0\r\n").await?;
+
+        if let Some(trailers) = &self.trailers {
+            for (name, value) in trailers.fields() {
+                out.write_all(format!("{}: {}\r\n", name, value).as_bytes())
+                    .await?;
+            }
+        }
+
+        out.write_all(b"\r\n").await?;
+        out.flush().await
+    }
+}
+
+impl Stream for StreamBody {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+impl fmt::Debug for StreamBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamBody")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+// Header fields sent after a chunked body. The handle can be cloned into
+// the body's stream, so fields like checksums can be set once the data
+// has been produced. Fields that aren't valid headers are dropped:
+#[derive(Debug, Clone, Default)]
+pub struct Trailers {
+    fields: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl Trailers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> &Self {
+        let mut fields = self.fields.lock().unwrap();
+        fields.retain(|(k, _)| !k.eq_ignore_ascii_case(name));
+        fields.push((name.to_owned(), value.to_owned()));
+        drop(fields);
+
+        self
+    }
+
+    fn fields(&self) -> Vec<(String, String)> {
+        let fields = self.fields.lock().unwrap();
+
+        fields
+            .iter()
+            .filter(|(name, value)| {
+                super::response::is_field(name, value)
+                    && !super::response::is_framing(name)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+pub(crate) trait SeekRead: AsyncRead + AsyncSeek + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> SeekRead for T {}
+
+// A body that can be read from any offset, so a range request can be
+// answered with just the bytes it asks for:
+pub(crate) struct SeekBody {
+    reader: Box<dyn SeekRead>,
+    pub(crate) length: u64,
+}
+
+impl SeekBody {
+    pub(crate) fn new<R>(reader: R, length: u64) -> Self
+    where
+        R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+    {
+        Self {
+            reader: Box::new(reader),
+            length,
+        }
+    }
+
+    // The whole body, from the start:
+    pub(crate) fn into_stream_body(self) -> StreamBody {
+        let length = self.length;
+        let reader = self.into_reader(vec![Part::Range(0..length)]);
+
+        let mut body = StreamBody::new(BodyStream::new(reader));
+        body.length = Some(length);
+        body
+    }
+
+    pub(crate) fn into_reader(self, parts: Vec<Part>) -> PartReader {
+        PartReader {
+            reader: self.reader,
+            parts: parts.into(),
+            seek: Seek::Needed,
+            chunk: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for SeekBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeekBody")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+// A piece of a body made from a seekable one:
+#[derive(Debug)]
+pub(crate) enum Part {
+    Bytes(Vec<u8>),
+    Range(Range<u64>),
+}
+
+impl Part {
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            Part::Bytes(bytes) => bytes.len() as u64,
+            Part::Range(range) => range.end - range.start,
+        }
+    }
+}
+
+enum Seek {
+    Needed,
+    Started,
+    Done,
+}
+
+// Reads the parts in order, seeking to the start of each range. Ranges
+// are read through `chunk`, which is allocated once and reused:
+pub(crate) struct PartReader {
+    reader: Box<dyn SeekRead>,
+    parts: VecDeque<Part>,
+    seek: Seek,
+    chunk: Vec<u8>,
+}
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+impl AsyncRead for PartReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // Nothing can be read, which mustn't look like the end of a range:
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let this = &mut *self;
+
+        loop {
+            let range = match this.parts.front_mut() {
+                None => return Poll::Ready(Ok(())),
+                Some(Part::Bytes(bytes)) if bytes.is_empty() => {
+                    this.parts.pop_front();
+                    continue;
+                }
+                Some(Part::Bytes(bytes)) => {
+                    let n = bytes.len().min(buf.remaining());
+                    buf.put_slice(&bytes[..n]);
+                    bytes.drain(..n);
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Part::Range(range)) if range.is_empty() => {
+                    this.parts.pop_front();
+                    this.seek = Seek::Needed;
+                    continue;
+                }
+                Some(Part::Range(range)) => range,
+            };
+
+            let mut reader = Pin::new(&mut this.reader);
+
+            if let Seek::Needed = this.seek {
+                reader.as_mut().start_seek(SeekFrom::Start(range.start))?;
+                this.seek = Seek::Started;
+            }
+
+            if let Seek::Started = this.seek {
+                ready!(reader.as_mut().poll_complete(cx))?;
+                this.seek = Seek::Done;
+            }
+
+            if this.chunk.is_empty() {
+                this.chunk = vec![0; CHUNK_SIZE];
+            }
+
+            let wanted = (range.end - range.start)
+                .min(buf.remaining() as u64)
+                .min(CHUNK_SIZE as u64) as usize;
+            let mut chunk_buf = ReadBuf::new(&mut this.chunk[..wanted]);
+            ready!(reader.poll_read(cx, &mut chunk_buf))?;
+
+            let read = chunk_buf.filled().len();
+            if read == 0 {
+                let error = io::ErrorKind::UnexpectedEof;
+                return Poll::Ready(Err(error.into()));
+            }
+
+            buf.put_slice(chunk_buf.filled());
+            range.start += read as u64;
+            return Poll::Ready(Ok(()));
+        }
+    }
+}
+
+// A stream of one chunk:
+pub(crate) struct Once(pub(crate) Option<Vec<u8>>);
+
+impl Stream for Once {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.0.take().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Part, SeekBody};
+
+    use std::future::poll_fn;
+    use std::io::Cursor;
+    use std::pin::Pin;
+    use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+    #[tokio::test]
+    async fn test_part_reader() {
+        let body = SeekBody::new(Cursor::new(b"0123456789".to_vec()), 10);
+        let mut reader = body.into_reader(vec![
+            Part::Range(2..5),
+            Part::Bytes(b"|".to_vec()),
+            Part::Range(7..10),
+        ]);
+
+        // A read with no room does nothing:
+        let mut empty = ReadBuf::new(&mut []);
+        poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut empty))
+            .await
+            .unwrap();
+
+        let mut read = String::new();
+        reader.read_to_string(&mut read).await.unwrap();
+        assert_eq!(read, "234|789");
+    }
+}
@@ -1,30 +1,342 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
 
-pub struct Cookie<'a> {
-    pub key: &'a str,
-    pub value: &'a str,
-    pub expires: Option<DateTime<Utc>>,
-    pub secure: bool,
-    pub http_only: bool,
+use super::response::http_date;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn to_str(&self) -> &str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+// A name that can't be used for a cookie. Names are HTTP tokens:
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidCookieName(String);
+
+impl fmt::Display for InvalidCookieName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cookie name: {:?}", self.0)
+    }
 }
 
-impl<'a> Cookie<'a> {
-    pub(super) fn to_string(&self) -> String {
-        let mut cookie = format!("{}={}", self.key, self.value);
+impl std::error::Error for InvalidCookieName {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    expires: Option<DateTime<Utc>>,
+    max_age: Option<Duration>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    partitioned: bool,
+}
+
+impl Cookie {
+    // Values may hold any text, characters that aren't allowed in a
+    // cookie are percent-encoded when the cookie is written:
+    pub fn new(name: &str, value: &str) -> Result<Self, InvalidCookieName> {
+        if name.is_empty() || !name.bytes().all(is_token) {
+            return Err(InvalidCookieName(name.to_owned()));
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            partitioned: false,
+        })
+    }
+
+    // A cookie that tells the browser to delete `name`. The path and
+    // domain need to match the ones the cookie was set with:
+    pub fn removal(name: &str) -> Result<Self, InvalidCookieName> {
+        Ok(Self::new(name, "")?.into_removal())
+    }
+
+    // A cookie that tells the browser to delete this one, with the same
+    // path and domain:
+    pub fn into_removal(mut self) -> Self {
+        self.with_value("")
+            .with_max_age(Duration::zero())
+            .with_expires(DateTime::UNIX_EPOCH);
+
+        self
+    }
+
+    pub fn with_value(&mut self, value: &str) -> &mut Self {
+        self.value = value.to_owned();
+        self
+    }
+
+    pub fn with_expires(&mut self, expires: DateTime<Utc>) -> &mut Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn with_max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    // Like the value, characters that aren't allowed in the domain and
+    // path are percent-encoded when the cookie is written. A domain that
+    // needed it won't match the host, so the browser ignores the cookie:
+    pub fn with_domain(&mut self, domain: &str) -> &mut Self {
+        self.domain = Some(domain.to_owned());
+        self
+    }
+
+    pub fn with_path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    pub fn with_secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn with_http_only(&mut self, http_only: bool) -> &mut Self {
+        self.http_only = http_only;
+        self
+    }
+
+    // Browsers drop `SameSite=None` cookies that aren't `Secure`, so this
+    // also marks the cookie as secure:
+    pub fn with_same_site(&mut self, same_site: SameSite) -> &mut Self {
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+
+        self.same_site = Some(same_site);
+        self
+    }
+
+    // Partitioned cookies must be `Secure` as well:
+    pub fn with_partitioned(&mut self, partitioned: bool) -> &mut Self {
+        if partitioned {
+            self.secure = true;
+        }
+
+        self.partitioned = partitioned;
+        self
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+
+    pub fn expires(&self) -> Option<&DateTime<Utc>> {
+        self.expires.as_ref()
+    }
+
+    pub fn max_age(&self) -> Option<&Duration> {
+        self.max_age.as_ref()
+    }
+
+    pub fn domain(&self) -> Option<&String> {
+        self.domain.as_ref()
+    }
+
+    pub fn path(&self) -> Option<&String> {
+        self.path.as_ref()
+    }
 
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn http_only(&self) -> bool {
+        self.http_only
+    }
+
+    pub fn same_site(&self) -> Option<&SameSite> {
+        self.same_site.as_ref()
+    }
+
+    pub fn partitioned(&self) -> bool {
+        self.partitioned
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}=", self.name)?;
+        write_encoded(f, &self.value, is_cookie_octet)?;
+
+        // Expires uses the IMF-fixdate format from RFC 7231:
         if let Some(expires) = self.expires {
-            let expires = expires.to_rfc2822();
-            cookie.push_str(&format!("; Expires={}", expires));
+            write!(f, "; Expires={}", http_date(expires))?;
         };
 
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.num_seconds().max(0))?;
+        }
+
+        if let Some(domain) = &self.domain {
+            f.write_str("; Domain=")?;
+            write_encoded(f, domain, is_attribute_octet)?;
+        }
+
+        if let Some(path) = &self.path {
+            f.write_str("; Path=")?;
+            write_encoded(f, path, is_attribute_octet)?;
+        }
+
         if self.secure {
-            cookie.push_str("; Secure")
+            f.write_str("; Secure")?;
         };
 
         if self.http_only {
-            cookie.push_str("; HttpOnly")
+            f.write_str("; HttpOnly")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.to_str())?;
         }
 
+        if self.partitioned {
+            f.write_str("; Partitioned")?;
+        }
+
+        Ok(())
+    }
+}
+
+// RFC 6265 cookie-octet, minus `%` so encoded values decode unambiguously:
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(
+        b,
+        0x21 | 0x23..=0x24 | 0x26..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B
+            | 0x5D..=0x7E
+    )
+}
+
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Visible characters and spaces, but not the `;` that ends an attribute.
+// Escapes in a path taken from a URL are left as they are:
+fn is_attribute_octet(b: u8) -> bool {
+    (0x20..0x7F).contains(&b) && b != b';'
+}
+
+fn write_encoded(
+    f: &mut fmt::Formatter<'_>,
+    input: &str,
+    allowed: fn(u8) -> bool,
+) -> fmt::Result {
+    for b in input.bytes() {
+        match allowed(b) {
+            true => write!(f, "{}", b as char)?,
+            false => write!(f, "%{:02X}", b)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cookie, SameSite};
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn test_attributes() {
+        let mut cookie = Cookie::new("session", "abc").unwrap();
+        cookie
+            .with_expires(Utc.with_ymd_and_hms(2022, 12, 1, 9, 5, 0).unwrap())
+            .with_max_age(Duration::hours(1))
+            .with_domain("example.com")
+            .with_path("/app")
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax);
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Expires=Thu, 01 Dec 2022 09:05:00 GMT; \
+             Max-Age=3600; Domain=example.com; Path=/app; HttpOnly; \
+             SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn test_same_site_none_and_partitioned_are_secure() {
+        let mut cookie = Cookie::new("embed", "1").unwrap();
+        cookie.with_same_site(SameSite::None).with_partitioned(true);
+
+        assert_eq!(
+            cookie.to_string(),
+            "embed=1; Secure; SameSite=None; Partitioned"
+        );
+    }
+
+    #[test]
+    fn test_value_encoding() {
+        let cookie = Cookie::new("note", "a b;c\"d%e,é").unwrap();
+
+        assert_eq!(cookie.to_string(), "note=a%20b%3Bc%22d%25e%2C%C3%A9");
+    }
+
+    #[test]
+    fn test_removal() {
+        let mut cookie = Cookie::removal("session").unwrap();
+        cookie.with_path("/");
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; \
+             Path=/"
+        );
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let error = Cookie::new("bad name", "value").unwrap_err();
+        assert_eq!(error.to_string(), "invalid cookie name: \"bad name\"");
+
+        assert!(Cookie::new("", "value").is_err());
+        assert!(Cookie::removal("a;b").is_err());
+    }
+
+    #[test]
+    fn test_attribute_encoding() {
+        let mut cookie = Cookie::new("session", "abc").unwrap();
         cookie
+            .with_path("/a;b\r\nc 100%")
+            .with_domain("example.com; Secure");
+
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Domain=example.com%3B Secure; \
+             Path=/a%3Bb%0D%0Ac 100%"
+        );
     }
 }
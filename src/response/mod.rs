@@ -1,7 +1,10 @@
+pub mod body;
 pub mod cookie;
+#[allow(clippy::module_inception)]
 pub mod response;
 pub mod status;
 
-pub use cookie::Cookie;
-pub use response::Response;
+pub use body::Trailers;
+pub use cookie::{Cookie, InvalidCookieName, SameSite};
+pub use response::{Response, ResponseError};
 pub use status::Status;
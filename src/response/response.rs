@@ -1,7 +1,45 @@
+use chrono::{DateTime, Utc};
+use futures_core::Stream;
 use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use tokio::io::{
+    self, AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt, BufWriter,
+};
 
+use super::body::{Once, SeekBody, StreamBody, Trailers};
 use super::cookie::Cookie;
 use super::status::Status;
+use crate::cookie_jar::CookieJar;
+use crate::redirect::{encode_location, Redirect};
+use crate::request::BodyStream;
+use crate::upgrade::{OnUpgrade, Upgraded};
+
+// Why a response can't be sent as it is:
+#[derive(Debug, PartialEq)]
+pub enum ResponseError {
+    InvalidStatus(u16),
+    InvalidHeaderName(String),
+    InvalidHeaderValue(String),
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseError::InvalidStatus(code) => {
+                write!(f, "invalid status: {}", code)
+            }
+            ResponseError::InvalidHeaderName(name) => {
+                write!(f, "invalid header name: {:?}", name)
+            }
+            ResponseError::InvalidHeaderValue(name) => {
+                write!(f, "invalid value for header {:?}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ResponseError {}
 
 #[derive(Debug)]
 pub struct Response {
@@ -9,6 +47,17 @@ pub struct Response {
     headers: HashMap<String, String>,
     cookies: Vec<String>,
     content: String,
+    stream: Option<StreamBody>,
+    seekable: Option<SeekBody>,
+    upgrade: Option<OnUpgrade>,
+    unmatched: bool,
+}
+
+// How the end of the body is marked:
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
 }
 
 impl Response {
@@ -18,30 +67,197 @@ impl Response {
             headers: HashMap::default(),
             cookies: Vec::new(),
             content: "".into(),
+            stream: None,
+            seekable: None,
+            upgrade: None,
+            unmatched: false,
         }
     }
 
+    // The router's answer when no route matches. `App::request` tells it
+    // apart from a 404 a handler or middleware made:
+    pub(crate) fn unmatched() -> Self {
+        let mut response = Self::new();
+        response
+            .with_status(Status::NotFound)
+            .with_content("Not found".to_owned());
+        response.unmatched = true;
+        response
+    }
+
+    // Sent in place of a response that can't be completed:
+    pub(crate) fn internal_error() -> Self {
+        let mut response = Self::new();
+        response
+            .with_status(Status::Internal)
+            .with_content("Internal server error".to_owned());
+        response
+    }
+
+    pub(crate) fn is_unmatched(&self) -> bool {
+        self.unmatched
+    }
+
+    // A redirect to `to`, a path or a full URL. Characters that can't be
+    // in a URL are percent-encoded:
+    pub fn redirect(to: &str, kind: Redirect) -> Self {
+        let mut response = Self::new();
+        response
+            .with_status(kind.status())
+            .with_header("Location", &encode_location(to));
+        response
+    }
+
     pub fn with_status(&mut self, status: Status) -> &mut Self {
         self.status = status;
         self
     }
 
+    // Content-Length and Transfer-Encoding are set when the response is
+    // written, values given here for them are ignored:
     pub fn with_header(&mut self, key: &str, value: &str) -> &mut Self {
         self.headers.insert(key.into(), value.into());
         self
     }
 
-    pub fn with_cookie(&mut self, cookie: Cookie) -> &mut Self {
+    pub fn with_cookie(&mut self, cookie: &Cookie) -> &mut Self {
         let cookie = cookie.to_string();
         self.cookies.push(cookie);
         self
     }
 
+    // Sets the cookies added to or removed from the jar:
+    pub fn with_cookie_jar(&mut self, jar: &CookieJar) -> &mut Self {
+        for cookie in jar.delta() {
+            self.with_cookie(cookie);
+        }
+        self
+    }
+
+    // A strong validator, sent quoted:
+    pub fn with_etag(&mut self, tag: &str) -> &mut Self {
+        self.with_header("ETag", &format!("\"{}\"", tag))
+    }
+
+    // A validator for responses that are equivalent, but may not be
+    // byte for byte the same:
+    pub fn with_weak_etag(&mut self, tag: &str) -> &mut Self {
+        self.with_header("ETag", &format!("W/\"{}\"", tag))
+    }
+
+    pub fn with_last_modified(&mut self, date: DateTime<Utc>) -> &mut Self {
+        self.with_header("Last-Modified", &http_date(date))
+    }
+
     pub fn with_content(&mut self, content: String) -> &mut Self {
         self.content = content;
+        self.stream = None;
+        self.seekable = None;
+        self
+    }
+
+    // Sends the body as the stream produces it, instead of `content`:
+    pub fn with_body_stream<S>(&mut self, stream: S) -> &mut Self
+    where
+        S: Stream<Item = io::Result<Vec<u8>>> + Send + 'static,
+    {
+        self.content.clear();
+        self.stream = Some(StreamBody::new(stream));
+        self.seekable = None;
+        self
+    }
+
+    pub fn with_body_reader<R>(&mut self, reader: R) -> &mut Self
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        self.with_body_stream(BodyStream::new(reader))
+    }
+
+    // A body that can be read from any offset, like a file. Range
+    // requests for it get just the bytes they ask for:
+    pub fn with_body_seekable<R>(&mut self, reader: R, length: u64) -> &mut Self
+    where
+        R: AsyncRead + AsyncSeek + Send + Unpin + 'static,
+    {
+        self.content.clear();
+        self.stream = None;
+        self.seekable = Some(SeekBody::new(reader, length));
+        self
+    }
+
+    // A body that is already in memory, but isn't text:
+    pub fn with_body_bytes(&mut self, bytes: Vec<u8>) -> &mut Self {
+        let length = bytes.len() as u64;
+        self.with_body_stream(Once(Some(bytes)))
+            .with_body_length(length)
+    }
+
+    // The length of the body, if it is known before it is sent:
+    pub(crate) fn body_length(&self) -> Option<u64> {
+        match (&self.stream, &self.seekable) {
+            (Some(stream), _) => stream.length,
+            (None, Some(body)) => Some(body.length),
+            (None, None) => Some(self.content.len() as u64),
+        }
+    }
+
+    // A streamed or seekable body, taken out to be sent some other way:
+    pub(crate) fn take_body_stream(&mut self) -> Option<StreamBody> {
+        match self.seekable.take() {
+            Some(body) => Some(body.into_stream_body()),
+            None => self.stream.take(),
+        }
+    }
+
+    pub(crate) fn seekable_length(&self) -> Option<u64> {
+        self.seekable.as_ref().map(|body| body.length)
+    }
+
+    pub(crate) fn take_seekable(&mut self) -> Option<SeekBody> {
+        self.seekable.take()
+    }
+
+    // The length of a streamed body, if it is known up front. Without
+    // it the body is sent with `Transfer-Encoding: chunked`:
+    pub fn with_body_length(&mut self, length: u64) -> &mut Self {
+        if let Some(stream) = &mut self.stream {
+            stream.length = Some(length);
+        }
+        self
+    }
+
+    // Fields sent after a chunked body, see `Trailers`:
+    pub fn with_trailers(&mut self, trailers: &Trailers) -> &mut Self {
+        if let Some(stream) = &mut self.stream {
+            stream.trailers = Some(trailers.clone());
+        }
         self
     }
 
+    // Hands the connection to `callback` once this response has been
+    // sent, instead of closing it. Only a `101 Switching Protocols`
+    // response is upgraded, and only if the request had no body:
+    pub fn with_upgrade<F, Fut>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.upgrade = Some(OnUpgrade::new(callback));
+        self
+    }
+
+    pub fn is_upgrade(&self) -> bool {
+        self.status == Status::SwitchingProtocols && self.upgrade.is_some()
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        match self.status {
+            Status::SwitchingProtocols => self.upgrade.take(),
+            _ => None,
+        }
+    }
+
     pub fn status(&self) -> &Status {
         &self.status
     }
@@ -50,6 +266,38 @@ impl Response {
         &self.headers
     }
 
+    // Header names are case-insensitive, unlike the keys of `headers()`:
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+
+    // A `304 Not Modified` standing in for this response. It keeps the
+    // headers a cache needs to update its copy, and the cookies:
+    pub(crate) fn into_not_modified(mut self) -> Self {
+        const KEEP: [&str; 6] = [
+            "Cache-Control",
+            "Content-Location",
+            "ETag",
+            "Expires",
+            "Last-Modified",
+            "Vary",
+        ];
+
+        self.headers.retain(|k, _| {
+            KEEP.iter().any(|keep| k.eq_ignore_ascii_case(keep))
+        });
+        self.status = Status::NotModified;
+        self.content.clear();
+        self.stream = None;
+        self.seekable = None;
+        self.upgrade = None;
+
+        self
+    }
+
     pub fn cookies(&self) -> &Vec<String> {
         &self.cookies
     }
@@ -58,34 +306,202 @@ impl Response {
         &self.content
     }
 
-    pub fn to_string(&self) -> String {
-        let mut raw = format!(
-            "HTTP/1.1 {}\r\nContent-Length: {}\r\n",
-            self.status.to_str(),
-            self.content.len(),
-        );
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some() || self.seekable.is_some()
+    }
+
+    // Checks the status line and headers can be sent without breaking
+    // the framing of the response:
+    pub fn validate(&self) -> Result<(), ResponseError> {
+        let code = self.status.as_u16();
+        if !(100..=999).contains(&code) || !is_field_value(self.status.reason())
+        {
+            Err(ResponseError::InvalidStatus(code))?
+        }
 
-        let mut headers = String::new();
+        for (name, value) in &self.headers {
+            if name.is_empty() || !name.bytes().all(is_token) {
+                Err(ResponseError::InvalidHeaderName(name.to_owned()))?
+            }
 
-        for (key, value) in &self.headers {
-            headers.push_str(&format!("{}: {}\r\n", key, value))
+            if !is_field_value(value) {
+                Err(ResponseError::InvalidHeaderValue(name.to_owned()))?
+            }
         }
 
         for cookie in &self.cookies {
-            headers.push_str(&format!("Set-Cookie: {}\r\n", cookie))
+            if !is_field_value(cookie) {
+                Err(ResponseError::InvalidHeaderValue("Set-Cookie".into()))?
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes the response to the connection, with a Date header unless
+    // one was set. `validate` should be checked first:
+    pub(crate) async fn write<W>(&mut self, stream: &mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        // Without a range request the whole body is sent:
+        if self.seekable.is_some() {
+            self.stream = self.take_body_stream();
+        }
+
+        let mut out = BufWriter::new(stream);
+
+        out.write_all(b"HTTP/1.1 ").await?;
+        out.write_all(self.status.as_u16().to_string().as_bytes())
+            .await?;
+        out.write_all(b" ").await?;
+        out.write_all(self.status.reason().as_bytes()).await?;
+        out.write_all(b"\r\n").await?;
+
+        match self.framing() {
+            Framing::Empty => {}
+            Framing::Length(length) => {
+                out.write_all(b"Content-Length: ").await?;
+                out.write_all(length.to_string().as_bytes()).await?;
+                out.write_all(b"\r\n").await?;
+            }
+            Framing::Chunked => {
+                out.write_all(b"Transfer-Encoding: chunked\r\n").await?;
+            }
         }
 
-        raw.push_str(&headers);
-        raw.push_str("\r\n");
-        raw.push_str(&self.content);
+        if !self.headers.keys().any(|k| k.eq_ignore_ascii_case("Date")) {
+            out.write_all(b"Date: ").await?;
+            out.write_all(http_date(Utc::now()).as_bytes()).await?;
+            out.write_all(b"\r\n").await?;
+        }
+
+        for (key, value) in self.fields() {
+            out.write_all(key.as_bytes()).await?;
+            out.write_all(b": ").await?;
+            out.write_all(value.as_bytes()).await?;
+            out.write_all(b"\r\n").await?;
+        }
+
+        out.write_all(b"\r\n").await?;
+
+        let framing = self.framing();
+        match (&mut self.stream, framing) {
+            (_, Framing::Empty) => {}
+            (Some(stream), Framing::Length(length)) => {
+                stream.write(&mut out, length).await?
+            }
+            (Some(stream), Framing::Chunked) => {
+                stream.write_chunked(&mut out).await?
+            }
+            (None, _) => out.write_all(self.content.as_bytes()).await?,
+        }
+
+        out.flush().await
+    }
+
+    // 1xx and 204 responses can't have a body or a Content-Length, and
+    // a 304 stands in for a body it doesn't send:
+    pub(crate) fn has_body(&self) -> bool {
+        let code = self.status.as_u16();
+        code >= 200 && code != 204 && code != 304
+    }
+
+    fn framing(&self) -> Framing {
+        if !self.has_body() {
+            return Framing::Empty;
+        }
+
+        match self.body_length() {
+            Some(length) => Framing::Length(length),
+            None => Framing::Chunked,
+        }
+    }
+
+    // The headers to send, without the framing headers we set ourselves,
+    // then the cookies:
+    fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        let headers = self
+            .headers
+            .iter()
+            .filter(|(key, _)| !is_framing(key))
+            .map(|(key, value)| (key.as_str(), value.as_str()));
+
+        let cookies = self.cookies.iter().map(|c| ("Set-Cookie", c.as_str()));
+
+        headers.chain(cookies)
+    }
+}
+
+// The IMF-fixdate format from RFC 7231, used by Date, Expires and
+// Last-Modified:
+pub(crate) fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
 
-        raw
+pub(crate) fn is_framing(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Content-Length")
+        || name.eq_ignore_ascii_case("Transfer-Encoding")
+}
+
+pub(crate) fn is_field(name: &str, value: &str) -> bool {
+    !name.is_empty() && name.bytes().all(is_token) && is_field_value(value)
+}
+
+fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+// Visible characters, spaces and tabs. CR and LF would end the field:
+fn is_field_value(value: &str) -> bool {
+    value.bytes().all(|b| {
+        b == b'\t' || b == b' ' || (0x21..=0x7E).contains(&b) || b >= 0x80
+    })
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The response as it would be sent, without the Date header so the
+// output doesn't change between calls. Headers aren't validated, and a
+// streamed body is left out:
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/1.1 {}\r\n", self.status)?;
+
+        match self.framing() {
+            Framing::Empty => {}
+            Framing::Length(length) => {
+                write!(f, "Content-Length: {}\r\n", length)?
+            }
+            Framing::Chunked => {
+                f.write_str("Transfer-Encoding: chunked\r\n")?
+            }
+        }
+
+        for (key, value) in self.fields() {
+            write!(f, "{}: {}\r\n", key, value)?;
+        }
+
+        f.write_str("\r\n")?;
+        if self.has_body() && !self.is_streaming() {
+            f.write_str(&self.content)?;
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Cookie, Response};
+    use super::{Cookie, Response, ResponseError};
+    use crate::response::Trailers;
+    use crate::Status;
+
+    use std::io::Cursor;
 
     use chrono::prelude::*;
 
@@ -95,29 +511,145 @@ mod test {
 Content-Length: 18\r\n\
 Content-Type: text/html\r\n\
 Set-Cookie: darkmode=true; Secure; HttpOnly\r\n\
-Set-Cookie: token=abcdefg; Expires=Thu, 01 Dec 2022 12:00:00 +0000; Secure; HttpOnly\r\n\r\n\
+Set-Cookie: token=abcdefg; Expires=Thu, 01 Dec 2022 12:00:00 GMT; Secure; HttpOnly\r\n\r\n\
 <h1> Welcome </h1>";
 
         let mut response = Response::new();
 
         response
             .with_header("Content-Type", "text/html")
-            .with_cookie(Cookie {
-                key: "darkmode",
-                value: "true",
-                expires: None,
-                secure: true,
-                http_only: true,
-            })
-            .with_cookie(Cookie {
-                key: "token",
-                value: "abcdefg",
-                expires: Some(chrono::Utc.ymd(2022, 12, 1).and_hms(12, 00, 00)),
-                secure: true,
-                http_only: true,
-            })
+            .with_cookie(
+                Cookie::new("darkmode", "true")
+                    .unwrap()
+                    .with_secure(true)
+                    .with_http_only(true),
+            )
+            .with_cookie(
+                Cookie::new("token", "abcdefg")
+                    .unwrap()
+                    .with_expires(
+                        chrono::Utc
+                            .with_ymd_and_hms(2022, 12, 1, 12, 0, 0)
+                            .unwrap(),
+                    )
+                    .with_secure(true)
+                    .with_http_only(true),
+            )
             .with_content("<h1> Welcome </h1>".to_owned());
 
         assert_eq!(response.to_string(), expected);
     }
+
+    #[test]
+    fn test_framing_headers_are_ours() {
+        let mut response = Response::new();
+        response
+            .with_header("content-length", "1000")
+            .with_header("Transfer-Encoding", "chunked")
+            .with_content("hi".to_owned());
+
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn test_no_body() {
+        let mut response = Response::new();
+        response
+            .with_status(Status::NoContent)
+            .with_content("ignored".to_owned());
+
+        assert_eq!(response.to_string(), "HTTP/1.1 204 No Content\r\n\r\n");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut response = Response::new();
+        response.with_header("Location", "/a\r\nSet-Cookie: admin=1");
+        assert_eq!(
+            response.validate(),
+            Err(ResponseError::InvalidHeaderValue("Location".to_owned()))
+        );
+
+        let mut response = Response::new();
+        response.with_header("Bad Name", "value");
+        assert_eq!(
+            response.validate(),
+            Err(ResponseError::InvalidHeaderName("Bad Name".to_owned()))
+        );
+
+        let mut response = Response::new();
+        response.with_status(Status::Custom(1000, "Too Big"));
+        assert_eq!(
+            response.validate(),
+            Err(ResponseError::InvalidStatus(1000))
+        );
+
+        assert_eq!(Response::new().validate(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_write() {
+        let mut response = Response::new();
+        response.with_content("hello".to_owned());
+
+        let mut written = Vec::new();
+        response.write(&mut written).await.unwrap();
+        let written = String::from_utf8(written).unwrap();
+
+        assert!(written
+            .starts_with("HTTP/1.1 200 OK\r\nContent-Length: 5\r\nDate: "));
+        assert!(written.ends_with(" GMT\r\n\r\nhello"));
+    }
+
+    async fn written(response: &mut Response) -> String {
+        let mut written = Vec::new();
+        response.write(&mut written).await.unwrap();
+
+        String::from_utf8(written).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chunked() {
+        let report = "id,name\n".repeat(2000);
+        let trailers = Trailers::new();
+
+        let mut response = Response::new();
+        response
+            .with_header("Content-Length", "5")
+            .with_body_reader(Cursor::new(report.clone().into_bytes()))
+            .with_trailers(&trailers);
+        trailers.set("X-Rows", "2000").set("Bad\r\nName", "x");
+
+        let written = written(&mut response).await;
+        let (head, body) = written.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("\r\nTransfer-Encoding: chunked"));
+        assert!(!head.contains("Content-Length"));
+
+        // 16000 bytes, read in 8 KiB chunks:
+        let expected = format!(
+            "2000\r\n{}\r\n1E80\r\n{}\r\n0\r\nX-Rows: 2000\r\n\r\n",
+            &report[..8192],
+            &report[8192..]
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_length() {
+        let mut response = Response::new();
+        response.with_body_reader(&b"hello"[..]).with_body_length(5);
+
+        let written = written(&mut response).await;
+        assert!(written.contains("\r\nContent-Length: 5\r\n"));
+        assert!(!written.contains("chunked"));
+        assert!(written.ends_with("\r\n\r\nhello"));
+
+        // A body that doesn't match its length can't be framed:
+        let mut response = Response::new();
+        response.with_body_reader(&b"hi"[..]).with_body_length(5);
+        assert!(response.write(&mut Vec::new()).await.is_err());
+    }
 }
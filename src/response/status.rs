@@ -1,22 +1,189 @@
-#[derive(Debug, PartialEq)]
-pub enum Status {
-    OK,
-    Created,
-    BadRequest,
-    NotFound,
-    ImATeaPot,
-    Internal,
+use std::fmt;
+
+// Generates the enum and the code and reason lookups from one table, so
+// the three can't drift apart:
+macro_rules! statuses {
+    ($($name:ident = ($code:literal, $reason:literal),)+) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Status {
+            $($name,)+
+            // Any other code, with its reason phrase. The code must be
+            // three digits. A custom status isn't equal to the named
+            // variant with the same code, compare `as_u16` instead:
+            Custom(u16, &'static str),
+        }
+
+        impl Status {
+            pub fn as_u16(&self) -> u16 {
+                match self {
+                    $(Status::$name => $code,)+
+                    Status::Custom(code, _) => *code,
+                }
+            }
+
+            pub fn reason(&self) -> &'static str {
+                match self {
+                    $(Status::$name => $reason,)+
+                    Status::Custom(_, reason) => reason,
+                }
+            }
+
+            // Unregistered codes become `Custom` with an empty reason.
+            // `None` if the code isn't three digits:
+            pub fn from_u16(code: u16) -> Option<Self> {
+                match code {
+                    $($code => Some(Status::$name),)+
+                    100..=999 => Some(Status::Custom(code, "")),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+statuses! {
+    Continue = (100, "Continue"),
+    SwitchingProtocols = (101, "Switching Protocols"),
+    Processing = (102, "Processing"),
+    EarlyHints = (103, "Early Hints"),
+    OK = (200, "OK"),
+    Created = (201, "Created"),
+    Accepted = (202, "Accepted"),
+    NonAuthoritativeInformation = (203, "Non-Authoritative Information"),
+    NoContent = (204, "No Content"),
+    ResetContent = (205, "Reset Content"),
+    PartialContent = (206, "Partial Content"),
+    MultiStatus = (207, "Multi-Status"),
+    AlreadyReported = (208, "Already Reported"),
+    ImUsed = (226, "IM Used"),
+    MultipleChoices = (300, "Multiple Choices"),
+    MovedPermanently = (301, "Moved Permanently"),
+    Found = (302, "Found"),
+    SeeOther = (303, "See Other"),
+    NotModified = (304, "Not Modified"),
+    UseProxy = (305, "Use Proxy"),
+    TemporaryRedirect = (307, "Temporary Redirect"),
+    PermanentRedirect = (308, "Permanent Redirect"),
+    BadRequest = (400, "Bad Request"),
+    Unauthorized = (401, "Unauthorized"),
+    PaymentRequired = (402, "Payment Required"),
+    Forbidden = (403, "Forbidden"),
+    NotFound = (404, "Not Found"),
+    MethodNotAllowed = (405, "Method Not Allowed"),
+    NotAcceptable = (406, "Not Acceptable"),
+    ProxyAuthenticationRequired = (407, "Proxy Authentication Required"),
+    RequestTimeout = (408, "Request Timeout"),
+    Conflict = (409, "Conflict"),
+    Gone = (410, "Gone"),
+    LengthRequired = (411, "Length Required"),
+    PreconditionFailed = (412, "Precondition Failed"),
+    PayloadTooLarge = (413, "Payload Too Large"),
+    UriTooLong = (414, "URI Too Long"),
+    UnsupportedMediaType = (415, "Unsupported Media Type"),
+    RangeNotSatisfiable = (416, "Range Not Satisfiable"),
+    ExpectationFailed = (417, "Expectation Failed"),
+    ImATeaPot = (418, "I'm a teapot"),
+    MisdirectedRequest = (421, "Misdirected Request"),
+    UnprocessableEntity = (422, "Unprocessable Entity"),
+    Locked = (423, "Locked"),
+    FailedDependency = (424, "Failed Dependency"),
+    TooEarly = (425, "Too Early"),
+    UpgradeRequired = (426, "Upgrade Required"),
+    PreconditionRequired = (428, "Precondition Required"),
+    TooManyRequests = (429, "Too Many Requests"),
+    RequestHeaderFieldsTooLarge = (431, "Request Header Fields Too Large"),
+    UnavailableForLegalReasons = (451, "Unavailable For Legal Reasons"),
+    Internal = (500, "Internal Server Error"),
+    NotImplemented = (501, "Not Implemented"),
+    BadGateway = (502, "Bad Gateway"),
+    ServiceUnavailable = (503, "Service Unavailable"),
+    GatewayTimeout = (504, "Gateway Timeout"),
+    HttpVersionNotSupported = (505, "HTTP Version Not Supported"),
+    VariantAlsoNegotiates = (506, "Variant Also Negotiates"),
+    InsufficientStorage = (507, "Insufficient Storage"),
+    LoopDetected = (508, "Loop Detected"),
+    NotExtended = (510, "Not Extended"),
+    NetworkAuthenticationRequired = (511, "Network Authentication Required"),
 }
 
 impl Status {
-    pub fn to_str(&self) -> &str {
-        match self {
-            Status::OK => "200 OK",
-            Status::Created => "201 Created",
-            Status::BadRequest => "400 Bad Request",
-            Status::NotFound => "404 Not Found",
-            Status::ImATeaPot => "418 I'm a teapot",
-            Status::Internal => "500 Internal Server Error",
+    // Same as `to_string`, kept for code written before `Display`:
+    #[deprecated(note = "use the `Display` impl, or `as_u16` and `reason`")]
+    pub fn to_str(&self) -> String {
+        format!("{} {}", self.as_u16(), self.reason())
+    }
+
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.as_u16())
+    }
+}
+
+// The code and reason, as sent in the status line:
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Status;
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(Status::NoContent.to_string(), "204 No Content");
+        assert_eq!(Status::Internal.as_u16(), 500);
+        assert_eq!(Status::from_u16(429), Some(Status::TooManyRequests));
+        assert_eq!(Status::from_u16(299), Some(Status::Custom(299, "")));
+        assert_eq!(Status::from_u16(42), None);
+
+        for code in 100..600 {
+            if let Some(status) = Status::from_u16(code) {
+                assert_eq!(status.as_u16(), code);
+            }
         }
     }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_to_str() {
+        assert_eq!(Status::OK.to_str(), "200 OK");
+        assert_eq!(Status::ImATeaPot.to_str(), "418 I'm a teapot");
+        assert_eq!(Status::Custom(299, "").to_str(), "299 ");
+    }
+
+    #[test]
+    fn test_custom() {
+        let status = Status::Custom(599, "Network Connect Timeout Error");
+
+        assert_eq!(status.to_string(), "599 Network Connect Timeout Error");
+        assert!(status.is_server_error());
+        assert_ne!(Status::Custom(404, "Not Found"), Status::NotFound);
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(Status::EarlyHints.is_informational());
+        assert!(Status::PartialContent.is_success());
+        assert!(Status::NotModified.is_redirection());
+        assert!(Status::Conflict.is_client_error());
+        assert!(!Status::Conflict.is_server_error());
+        assert!(Status::ServiceUnavailable.is_server_error());
+    }
 }
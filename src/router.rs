@@ -1,49 +1,380 @@
-use crate::request::{Method, Request};
-use crate::response::Response;
+use crate::redirect::{same_site, Redirect};
+use crate::request::{BodyTooLarge, Method, Request};
+use crate::response::{Response, Status};
 
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio::sync::RwLock;
 
-type Handler = fn(Request) -> Response;
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+pub(crate) type Handler =
+    Arc<dyn Fn(Request) -> BoxFuture<Response> + Send + Sync>;
+
+// Something that answers every request under a mounted prefix, see
+// `Router::mount`:
+pub trait Endpoint: Send + Sync + 'static {
+    fn call(&self, request: Request) -> BoxFuture<Response>;
+}
+
+impl<F> Endpoint for F
+where
+    F: Fn(Request) -> BoxFuture<Response> + Send + Sync + 'static,
+{
+    fn call(&self, request: Request) -> BoxFuture<Response> {
+        self(request)
+    }
+}
+
+// The part of the path below the prefix an endpoint was mounted at:
+#[derive(Debug, Clone)]
+pub(crate) struct MountPath(pub(crate) String);
+
+// What a route does with a request whose path only differs from it in
+// a trailing slash, like `/potato/` for `/potato`:
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TrailingSlash {
+    // The request isn't for this route:
+    #[default]
+    Strict,
+    // The client is sent to the route's own path with a 308:
+    Redirect,
+    // The route answers both:
+    MatchBoth,
+}
+
+struct Route {
+    handle: Handler,
+    trailing_slash: TrailingSlash,
+}
+
+#[derive(Default)]
+pub(crate) struct RouteMap {
+    routes: HashMap<String, Route>,
+    mounts: Vec<(String, Handler)>,
+}
+
+impl RouteMap {
+    // An exact route, then one that differs in a trailing slash, or else
+    // the mount with the longest prefix of the request's path. A mount
+    // also gets the rest of the path:
+    pub(crate) fn find(
+        &self,
+        request: &Request,
+    ) -> Option<(Handler, Option<MountPath>)> {
+        if let Some(route) = self.routes.get(request.route_key()) {
+            return Some((route.handle.clone(), None));
+        }
+
+        if let Some(handle) = self.find_other_slash(request) {
+            return Some((handle, None));
+        }
+
+        let path = request.path();
+        self.mounts
+            .iter()
+            .filter_map(|(prefix, handle)| {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                match rest.is_empty() || rest.starts_with('/') {
+                    true => Some((prefix, handle, rest)),
+                    false => None,
+                }
+            })
+            .max_by_key(|(prefix, _, _)| prefix.len())
+            .map(|(_, handle, rest)| {
+                (handle.clone(), Some(MountPath(rest.to_owned())))
+            })
+    }
+
+    // A route whose path only differs in a trailing slash, if its policy
+    // lets it answer the request:
+    fn find_other_slash(&self, request: &Request) -> Option<Handler> {
+        let path = request.path();
+        let other = match path.strip_suffix('/') {
+            Some("") => return None,
+            Some(path) => path.to_owned(),
+            None => format!("{}/", path),
+        };
+
+        let key =
+            format!("{:?} {} {}", request.method(), other, request.version());
+        let route = self.routes.get(&key)?;
+
+        match route.trailing_slash {
+            TrailingSlash::Strict => None,
+            TrailingSlash::MatchBoth => Some(route.handle.clone()),
+            TrailingSlash::Redirect => {
+                Some(Arc::new(move |request: Request| {
+                    let location = same_site(&other, &request);
+                    Box::pin(async move {
+                        Response::redirect(&location, Redirect::Permanent)
+                    })
+                }))
+            }
+        }
+    }
+}
 
-type RouteMap = HashMap<String, Handler>;
 pub(crate) type Routes = Arc<RwLock<RouteMap>>;
 
+// A route waiting for `build`. Its trailing slash policy is `None` until
+// one is set on its router, or the router it is nested in:
+struct PendingRoute {
+    method: String,
+    path: String,
+    handle: Handler,
+    trailing_slash: Option<TrailingSlash>,
+}
+
 pub struct Router {
     pub(crate) routes: Routes,
-    before_routes: Vec<(String, Handler)>,
+    before_routes: Vec<PendingRoute>,
+    before_mounts: Vec<(String, Handler)>,
+    trailing_slash: Option<TrailingSlash>,
 }
 
 impl Router {
     pub fn new() -> Self {
         Self {
-            routes: Arc::new(RwLock::new(HashMap::new())),
+            routes: Arc::new(RwLock::new(RouteMap::default())),
             before_routes: Vec::new(),
+            before_mounts: Vec::new(),
+            trailing_slash: None,
         }
     }
 
+    // How this router's routes treat a trailing slash they weren't added
+    // with. Routes of nested routers follow it unless those set their own:
+    pub fn with_trailing_slash(&mut self, policy: TrailingSlash) -> &mut Self {
+        self.trailing_slash = Some(policy);
+        self
+    }
+
     pub fn add(
         &mut self,
         method: Method,
         route: &str,
-        handle: Handler,
+        handle: fn(Request) -> Response,
+    ) -> &mut Self {
+        self.add_async(
+            method,
+            route,
+            move |request| async move { handle(request) },
+        )
+    }
+
+    pub fn add_async<F, Fut>(
+        &mut self,
+        method: Method,
+        route: &str,
+        handle: F,
+    ) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        let handle = Arc::new(handle);
+
+        // The body is read into memory before the handler runs:
+        self.insert(method, route, move |mut request: Request| {
+            let handle = handle.clone();
+            Box::pin(async move {
+                match request.buffer_body().await {
+                    Ok(()) => {}
+                    Err(e) if BodyTooLarge::is(&e) => {
+                        let mut response = Response::new();
+                        response
+                            .with_status(Status::PayloadTooLarge)
+                            .with_content("Payload too large".to_owned());
+                        return response;
+                    }
+                    Err(_) => {
+                        let mut response = Response::new();
+                        response
+                            .with_status(Status::BadRequest)
+                            .with_content("Bad request".to_owned());
+                        return response;
+                    }
+                }
+
+                handle(request).await
+            })
+        })
+    }
+
+    // Like `add_async`, but the body is left unread for the handler to
+    // consume through `Request::take_body_stream`:
+    pub fn add_streaming<F, Fut>(
+        &mut self,
+        method: Method,
+        route: &str,
+        handle: F,
+    ) -> &mut Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Response> + Send + 'static,
+    {
+        self.insert(method, route, move |request| Box::pin(handle(request)))
+    }
+
+    // Sends every request whose path is `prefix`, or below it, to
+    // `endpoint`, whatever its method. Routes added with `add` take
+    // precedence, and the body is left unread:
+    pub fn mount(
+        &mut self,
+        prefix: &str,
+        endpoint: impl Endpoint,
     ) -> &mut Self {
-        assert!(route.starts_with("/"));
+        assert!(prefix.starts_with('/'));
 
-        let route_key = format!("{:?} {} HTTP/1.1", method, route);
-        self.before_routes.push((route_key, handle));
+        let prefix = prefix.trim_end_matches('/').to_owned();
+        let endpoint = Arc::new(endpoint);
+        self.before_mounts
+            .push((prefix, Arc::new(move |request| endpoint.call(request))));
+
+        self
+    }
+
+    // Adds the routes and mounts of `router` below `prefix`, so its `/`
+    // becomes `prefix` and its `/potato` becomes `prefix/potato`:
+    pub fn nest(&mut self, prefix: &str, mut router: Router) -> &mut Self {
+        assert!(prefix.starts_with('/'));
+
+        let prefix = prefix.trim_end_matches('/');
+
+        for mut route in router.before_routes.drain(..) {
+            route.path = match route.path.as_str() {
+                "/" if !prefix.is_empty() => prefix.to_owned(),
+                path => format!("{}{}", prefix, path),
+            };
+            route.trailing_slash =
+                route.trailing_slash.or(router.trailing_slash);
+            self.before_routes.push(route);
+        }
+
+        for (mount, handle) in router.before_mounts.drain(..) {
+            self.before_mounts
+                .push((format!("{}{}", prefix, mount), handle));
+        }
+
+        self
+    }
+
+    fn insert<F>(&mut self, method: Method, route: &str, handle: F) -> &mut Self
+    where
+        F: Fn(Request) -> BoxFuture<Response> + Send + Sync + 'static,
+    {
+        assert!(route.starts_with('/'));
+
+        self.before_routes.push(PendingRoute {
+            method: format!("{:?}", method),
+            path: route.to_owned(),
+            handle: Arc::new(handle),
+            trailing_slash: None,
+        });
 
         self
     }
 
     pub(crate) async fn build(&mut self) {
-        while let Some((key, handle)) = self.before_routes.pop() {
-            self.routes.write().await.insert(key.to_owned(), handle);
+        let mut routes = self.routes.write().await;
+        let default = self.trailing_slash.unwrap_or_default();
+
+        while let Some(route) = self.before_routes.pop() {
+            let key = format!("{} {} HTTP/1.1", route.method, route.path);
+            let route = Route {
+                handle: route.handle,
+                trailing_slash: route.trailing_slash.unwrap_or(default),
+            };
+            routes.routes.insert(key, route);
         }
+
+        routes.mounts.append(&mut self.before_mounts);
+    }
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Router, TrailingSlash};
+    use crate::app::send_request;
+    use crate::{App, Method, Request, Response, Status};
+
+    fn potato(_: Request) -> Response {
+        let mut response = Response::new();
+        response.with_content("potato".to_owned());
+        response
     }
 
-    pub(crate) async fn get_routes(&self) -> RwLockReadGuard<'_, RouteMap> {
-        self.routes.read().await
+    async fn get(app: &mut App, target: &str) -> Option<Response> {
+        send_request(app, Method::GET, target, &[]).await.ok()
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash() {
+        let mut router = Router::new();
+        router.add(Method::GET, "/potato", potato);
+        let mut app = App::new(router);
+
+        assert!(get(&mut app, "/potato").await.is_some());
+        assert!(get(&mut app, "/potato/").await.is_none());
+
+        let mut router = Router::new();
+        router.with_trailing_slash(TrailingSlash::MatchBoth).add(
+            Method::GET,
+            "/potato",
+            potato,
+        );
+        let mut app = App::new(router);
+
+        let response = get(&mut app, "/potato/").await.unwrap();
+        assert_eq!(response.content(), "potato");
+
+        let mut router = Router::new();
+        router
+            .add(Method::GET, "/potato/", potato)
+            .with_trailing_slash(TrailingSlash::Redirect);
+        let mut app = App::new(router);
+
+        let response = get(&mut app, "/potato?x=1").await.unwrap();
+        assert_eq!(response.status(), &Status::PermanentRedirect);
+        assert_eq!(response.header("Location").unwrap(), "/potato/?x=1");
+    }
+
+    #[tokio::test]
+    async fn test_nest() {
+        let mut api = Router::new();
+        api.with_trailing_slash(TrailingSlash::MatchBoth)
+            .add(Method::GET, "/", potato)
+            .add(Method::GET, "/potato", potato);
+
+        let mut admin = Router::new();
+        admin.add(Method::GET, "/potato", potato);
+
+        let mut router = Router::new();
+        router
+            .with_trailing_slash(TrailingSlash::Redirect)
+            .nest("/api/", api)
+            .nest("/admin", admin);
+        let mut app = App::new(router);
+
+        let response = get(&mut app, "/api").await.unwrap();
+        assert_eq!(response.content(), "potato");
+
+        let response = get(&mut app, "/api/potato/").await.unwrap();
+        assert_eq!(response.content(), "potato");
+
+        // Nested routers without a policy follow the outer one:
+        let response = get(&mut app, "/admin/potato/").await.unwrap();
+        assert_eq!(response.header("Location").unwrap(), "/admin/potato");
+
+        assert!(get(&mut app, "/potato").await.is_none());
     }
 }
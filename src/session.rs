@@ -0,0 +1,595 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::middleware::{Middleware, Next};
+use crate::request::query::percent_decode;
+use crate::request::Request;
+use crate::response::{Cookie, InvalidCookieName, Response, SameSite, Status};
+use crate::router::BoxFuture;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub type SessionData = HashMap<String, String>;
+
+// How often `MemoryStore` drops expired sessions nobody asked for again:
+const SWEEP_INTERVAL: Duration = Duration::minutes(1);
+
+#[derive(Default)]
+struct Entries {
+    sessions: HashMap<String, (SessionData, DateTime<Utc>)>,
+    next_sweep: DateTime<Utc>,
+}
+
+// Where sessions are kept between requests. A session past its expiry
+// must not be loaded again:
+pub trait SessionStore: Send + Sync + 'static {
+    fn load(&self, id: String) -> BoxFuture<io::Result<Option<SessionData>>>;
+
+    fn store(
+        &self,
+        id: String,
+        data: SessionData,
+        expires: DateTime<Utc>,
+    ) -> BoxFuture<io::Result<()>>;
+
+    fn destroy(&self, id: String) -> BoxFuture<io::Result<()>>;
+}
+
+// Keeps sessions in memory. They are lost when the process exits:
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    sessions: Arc<Mutex<Entries>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: String) -> BoxFuture<io::Result<Option<SessionData>>> {
+        let sessions = &mut self.sessions.lock().unwrap().sessions;
+
+        let data = match sessions.get(&id) {
+            Some((data, expires)) if *expires > Utc::now() => {
+                Some(data.clone())
+            }
+            Some(_) => {
+                sessions.remove(&id);
+                None
+            }
+            None => None,
+        };
+
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn store(
+        &self,
+        id: String,
+        data: SessionData,
+        expires: DateTime<Utc>,
+    ) -> BoxFuture<io::Result<()>> {
+        let mut entries = self.sessions.lock().unwrap();
+
+        // Expired sessions are dropped when they are loaded, and the rest
+        // now and then as new ones come in:
+        let now = Utc::now();
+        if now >= entries.next_sweep {
+            entries.sessions.retain(|_, (_, expires)| *expires > now);
+            entries.next_sweep = now + SWEEP_INTERVAL;
+        }
+        entries.sessions.insert(id, (data, expires));
+
+        Box::pin(async { Ok(()) })
+    }
+
+    fn destroy(&self, id: String) -> BoxFuture<io::Result<()>> {
+        self.sessions.lock().unwrap().sessions.remove(&id);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// Keeps each session in its own file in `dir`. The first line holds
+// the expiry, then one percent-encoded `key\tvalue` pair per line:
+#[derive(Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // Only IDs we could have issued name a file, anything else could
+    // point outside `dir`:
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        match is_session_id(id) {
+            true => Some(self.dir.join(format!("{}.session", id))),
+            false => None,
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: String) -> BoxFuture<io::Result<Option<SessionData>>> {
+        let path = match self.path(&id) {
+            Some(path) => path,
+            None => return Box::pin(async { Ok(None) }),
+        };
+
+        Box::pin(async move {
+            let contents = match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Ok(None)
+                }
+                Err(e) => Err(e)?,
+            };
+
+            let mut lines = contents.lines();
+            let expires = lines
+                .next()
+                .and_then(|line| DateTime::parse_from_rfc3339(line).ok());
+
+            match expires {
+                Some(expires) if expires > Utc::now() => {}
+                _ => {
+                    let _ = tokio::fs::remove_file(&path).await;
+                    return Ok(None);
+                }
+            }
+
+            let data = lines
+                .filter_map(|line| line.split_once('\t'))
+                .map(|(k, v)| (percent_decode(k), percent_decode(v)))
+                .collect();
+
+            Ok(Some(data))
+        })
+    }
+
+    fn store(
+        &self,
+        id: String,
+        data: SessionData,
+        expires: DateTime<Utc>,
+    ) -> BoxFuture<io::Result<()>> {
+        let path = match self.path(&id) {
+            Some(path) => path,
+            None => return Box::pin(async { Err(invalid_id()) }),
+        };
+        let dir = self.dir.clone();
+
+        Box::pin(async move {
+            let mut contents = expires.to_rfc3339();
+            for (key, value) in data {
+                contents.push('\n');
+                contents.push_str(&encode(&key));
+                contents.push('\t');
+                contents.push_str(&encode(&value));
+            }
+
+            // Written to a temporary file first, so a session is never
+            // read half written. Each write gets its own, so two requests
+            // saving the same session don't write into one file:
+            tokio::fs::create_dir_all(&dir).await?;
+            let temp =
+                path.with_extension(format!("{:016x}.tmp", OsRng.next_u64()));
+            if let Err(e) = tokio::fs::write(&temp, contents).await {
+                let _ = tokio::fs::remove_file(&temp).await;
+                return Err(e);
+            }
+            tokio::fs::rename(&temp, &path).await
+        })
+    }
+
+    fn destroy(&self, id: String) -> BoxFuture<io::Result<()>> {
+        let path = match self.path(&id) {
+            Some(path) => path,
+            None => return Box::pin(async { Ok(()) }),
+        };
+
+        Box::pin(async move {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+fn invalid_id() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "invalid session ID")
+}
+
+fn encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '%' | '\t' | '\n' | '\r' => {
+                out.push_str(&format!("%{:02X}", c as u8))
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[derive(Debug, Default)]
+struct State {
+    data: SessionData,
+    changed: bool,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+// The current request's session. Handlers get it with
+// `request.extract::<Session>()`, changes are saved once the response
+// has been built:
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    fn new(data: SessionData) -> Self {
+        let state = State {
+            data,
+            ..State::default()
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap().data.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, value: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.changed = true;
+        state.data.insert(key.to_owned(), value.to_owned())
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.changed = true;
+        state.data.remove(key)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.changed = true;
+        state.data.clear();
+    }
+
+    // Moves the session to a new ID, keeping its data. Call this when a
+    // user logs in, so an ID planted before login is useless after it:
+    pub fn regenerate(&self) {
+        self.state.lock().unwrap().regenerate = true;
+    }
+
+    // Deletes the session from the store and the client:
+    pub fn destroy(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.destroyed = true;
+        state.data.clear();
+    }
+}
+
+impl FromRequest for Session {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        match request.extensions().get::<Session>() {
+            Some(session) => Ok(session.clone()),
+            None => Err(Rejection::new(
+                Status::Internal,
+                "Sessions middleware is not installed",
+            )),
+        }
+    }
+}
+
+// Loads the session named by the session cookie before the request is
+// handled, and saves it after. A new session only gets a cookie once
+// something is stored in it:
+pub struct Sessions<S: SessionStore> {
+    store: Arc<S>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+}
+
+impl<S: SessionStore> Sessions<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store: Arc::new(store),
+            cookie_name: "session".to_owned(),
+            ttl: Duration::days(1),
+            secure: false,
+        }
+    }
+
+    pub fn with_cookie_name(&mut self, name: &str) -> &mut Self {
+        self.cookie_name = name.to_owned();
+        self
+    }
+
+    // How long a session lives after it was last changed. Reading a
+    // session doesn't extend it, so a user who only reads is signed out
+    // `ttl` after the last write. Insert something on each request to
+    // keep active sessions alive instead:
+    pub fn with_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    fn cookie(&self) -> Result<Cookie, InvalidCookieName> {
+        let mut cookie = Cookie::new(&self.cookie_name, "")?;
+        cookie
+            .with_path("/")
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax)
+            .with_secure(self.secure)
+            .with_max_age(self.ttl);
+
+        Ok(cookie)
+    }
+}
+
+impl<S: SessionStore> Middleware for Sessions<S> {
+    fn handle(&self, mut request: Request, next: Next) -> BoxFuture<Response> {
+        let store = self.store.clone();
+        let ttl = self.ttl;
+
+        // IDs are only looked up if they could have been issued by us:
+        let id = request
+            .cookies()
+            .get(&self.cookie_name)
+            .filter(|id| is_session_id(id))
+            .cloned();
+
+        // A cookie name that can't be sent is a configuration error:
+        let mut cookie = match self.cookie() {
+            Ok(cookie) => cookie,
+            Err(_) => return Box::pin(async { Response::internal_error() }),
+        };
+        let removal = cookie.clone().into_removal();
+
+        Box::pin(async move {
+            let loaded = match id {
+                Some(id) => match store.load(id.clone()).await {
+                    Ok(Some(data)) => Some((id, data)),
+                    Ok(None) => None,
+                    Err(_) => return Response::internal_error(),
+                },
+                None => None,
+            };
+
+            let (id, session) = match loaded {
+                Some((id, data)) => (Some(id), Session::new(data)),
+                None => (None, Session::default()),
+            };
+
+            request.extensions_mut().insert(session.clone());
+            let mut response = next.run(request).await;
+
+            let state = std::mem::take(&mut *session.state.lock().unwrap());
+
+            if state.destroyed || (state.changed && state.data.is_empty()) {
+                if let Some(id) = id {
+                    if store.destroy(id).await.is_err() {
+                        return Response::internal_error();
+                    }
+                    response.with_cookie(&removal);
+                }
+                return response;
+            }
+
+            if !state.changed && !state.regenerate {
+                return response;
+            }
+
+            let id = match (id, state.regenerate) {
+                (Some(id), false) => id,
+                (Some(old), true) => {
+                    if store.destroy(old).await.is_err() {
+                        return Response::internal_error();
+                    }
+                    new_session_id()
+                }
+                (None, _) => new_session_id(),
+            };
+
+            let expires = Utc::now() + ttl;
+            if store.store(id.clone(), state.data, expires).await.is_err() {
+                return Response::internal_error();
+            }
+
+            response.with_cookie(cookie.with_value(&id));
+            response
+        })
+    }
+}
+
+// 32 random bytes, base64 encoded:
+fn new_session_id() -> String {
+    let mut id = [0; 32];
+    OsRng.fill_bytes(&mut id);
+
+    URL_SAFE_NO_PAD.encode(id)
+}
+
+fn is_session_id(id: &str) -> bool {
+    id.len() == 43
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        new_session_id, FileStore, MemoryStore, Session, SessionStore, Sessions,
+    };
+    use crate::app::send_request;
+    use crate::{App, Method, Request, Response, Router};
+
+    use chrono::{Duration, Utc};
+    use std::collections::HashMap;
+
+    fn login(request: Request) -> Response {
+        let session = request.extract::<Session>().unwrap();
+        session.insert("user", "bob");
+        session.regenerate();
+
+        Response::new()
+    }
+
+    fn whoami(request: Request) -> Response {
+        let session = request.extract::<Session>().unwrap();
+
+        let mut response = Response::new();
+        response.with_content(session.get("user").unwrap_or_default());
+        response
+    }
+
+    fn logout(request: Request) -> Response {
+        request.extract::<Session>().unwrap().destroy();
+        Response::new()
+    }
+
+    fn app(store: impl SessionStore) -> App {
+        let mut router = Router::new();
+        router
+            .add(Method::POST, "/login", login)
+            .add(Method::GET, "/whoami", whoami)
+            .add(Method::POST, "/logout", logout);
+
+        let mut app = App::new(router);
+        app.with_middleware(Sessions::new(store));
+        app
+    }
+
+    async fn send(
+        app: &mut App,
+        method: Method,
+        path: &str,
+        cookie: &str,
+    ) -> Response {
+        let headers = [("Cookie", cookie)];
+        send_request(app, method, path, &headers).await.unwrap()
+    }
+
+    // The `name=value` part of the first Set-Cookie header:
+    fn session_cookie(response: &Response) -> String {
+        let cookie = &response.cookies()[0];
+        cookie.split(';').next().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_login_and_logout() {
+        let mut app = app(MemoryStore::new());
+
+        let response = send(&mut app, Method::GET, "/whoami", "").await;
+        assert_eq!(response.content(), "");
+        assert!(response.cookies().is_empty());
+
+        let response = send(&mut app, Method::POST, "/login", "").await;
+        let cookie = session_cookie(&response);
+        assert!(response.cookies()[0].contains("HttpOnly; SameSite=Lax"));
+
+        let response = send(&mut app, Method::GET, "/whoami", &cookie).await;
+        assert_eq!(response.content(), "bob");
+
+        let response = send(&mut app, Method::POST, "/logout", &cookie).await;
+        assert!(response.cookies()[0].contains("Max-Age=0"));
+
+        let response = send(&mut app, Method::GET, "/whoami", &cookie).await;
+        assert_eq!(response.content(), "");
+    }
+
+    #[tokio::test]
+    async fn test_regenerate() {
+        let mut app = app(MemoryStore::new());
+
+        let response = send(&mut app, Method::POST, "/login", "").await;
+        let first = session_cookie(&response);
+
+        let response = send(&mut app, Method::POST, "/login", &first).await;
+        let second = session_cookie(&response);
+        assert_ne!(first, second);
+
+        let response = send(&mut app, Method::GET, "/whoami", &first).await;
+        assert_eq!(response.content(), "");
+
+        let response = send(&mut app, Method::GET, "/whoami", &second).await;
+        assert_eq!(response.content(), "bob");
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let store = MemoryStore::new();
+        let data = HashMap::from([("user".to_owned(), "bob".to_owned())]);
+        let past = Utc::now() - Duration::seconds(1);
+
+        store.store("old".to_owned(), data, past).await.unwrap();
+        assert_eq!(store.load("old".to_owned()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let dir = std::env::temp_dir()
+            .join(format!("potato-sessions-{}", std::process::id()));
+        let store = FileStore::new(&dir);
+
+        let data = HashMap::from([(
+            "note".to_owned(),
+            "tabs\tand\nnew lines, 100%".to_owned(),
+        )]);
+        let expires = Utc::now() + Duration::hours(1);
+
+        let id = new_session_id();
+        store
+            .store(id.clone(), data.clone(), expires)
+            .await
+            .unwrap();
+        assert_eq!(store.load(id.clone()).await.unwrap(), Some(data.clone()));
+
+        store.destroy(id.clone()).await.unwrap();
+        assert_eq!(store.load(id).await.unwrap(), None);
+
+        // IDs that didn't come from us never name a file:
+        let escape = "../escape".to_owned();
+        assert!(store.store(escape.clone(), data, expires).await.is_err());
+        assert_eq!(store.load(escape.clone()).await.unwrap(), None);
+        store.destroy(escape).await.unwrap();
+        assert!(!dir.parent().unwrap().join("escape.session").exists());
+
+        let mut app = app(FileStore::new(&dir));
+        let response = send(&mut app, Method::POST, "/login", "").await;
+        let cookie = session_cookie(&response);
+
+        let response = send(&mut app, Method::GET, "/whoami", &cookie).await;
+        assert_eq!(response.content(), "bob");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
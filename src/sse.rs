@@ -0,0 +1,336 @@
+use crate::response::Response;
+
+use futures_core::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io;
+use tokio::sync::mpsc;
+use tokio::time::{self, Interval, MissedTickBehavior};
+
+// One `text/event-stream` frame. Data may span lines, each line is sent
+// as its own `data:` field:
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The event name and ID can't be split over lines like the data, so
+    // ones with a line break are refused:
+    pub fn with_event(
+        &mut self,
+        event: &str,
+    ) -> Result<&mut Self, InvalidField> {
+        if !is_single_line(event) {
+            return Err(InvalidField("event"));
+        }
+
+        self.event = Some(event.to_owned());
+        Ok(self)
+    }
+
+    // Browsers ignore IDs with a NUL in them:
+    pub fn with_id(&mut self, id: &str) -> Result<&mut Self, InvalidField> {
+        if !is_single_line(id) || id.contains('\0') {
+            return Err(InvalidField("id"));
+        }
+
+        self.id = Some(id.to_owned());
+        Ok(self)
+    }
+
+    pub fn with_data(&mut self, data: &str) -> &mut Self {
+        self.data = Some(data.to_owned());
+        self
+    }
+
+    // How long the browser waits before reconnecting:
+    pub fn with_retry(&mut self, retry: Duration) -> &mut Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // Comments are ignored by the browser. Like data, each line is sent
+    // as its own comment:
+    pub fn with_comment(&mut self, comment: &str) -> &mut Self {
+        self.comment = Some(comment.to_owned());
+        self
+    }
+}
+
+// A field value that can't be sent in an event:
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidField(&'static str);
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid event {}: must be a single line", self.0)
+    }
+}
+
+impl std::error::Error for InvalidField {}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                writeln!(f, ": {}", line)?;
+            }
+        }
+
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+
+        if let Some(data) = &self.data {
+            for line in lines(data) {
+                writeln!(f, "data: {}", line)?;
+            }
+        }
+
+        f.write_str("\n")
+    }
+}
+
+fn is_single_line(value: &str) -> bool {
+    !value.contains(['\r', '\n'])
+}
+
+// Splits on every line ending the browser knows, CRLF, LF and a bare CR:
+fn lines(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split("\r\n")
+        .flat_map(|line| line.split(['\r', '\n']))
+}
+
+// The client went away, so the event can't be sent:
+#[derive(Debug, PartialEq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("client disconnected")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+// Sends events to the client of an `Sse` made with `Sse::channel`:
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    tx: mpsc::Sender<Event>,
+}
+
+impl EventSender {
+    pub async fn send(&self, event: Event) -> Result<(), Disconnected> {
+        self.tx.send(event).await.map_err(|_| Disconnected)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    // Resolves once the client has disconnected:
+    pub async fn closed(&self) {
+        self.tx.closed().await
+    }
+}
+
+type Events = Pin<Box<dyn Stream<Item = Event> + Send>>;
+
+// A response that stays open and sends events as they happen. A comment
+// is sent when nothing else has been for a while, which keeps proxies
+// from closing the connection and notices clients that have gone:
+pub struct Sse {
+    events: Events,
+    keep_alive: Option<Duration>,
+}
+
+impl Sse {
+    pub fn new<S>(events: S) -> Self
+    where
+        S: Stream<Item = Event> + Send + 'static,
+    {
+        Self {
+            events: Box::pin(events),
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    // An `Sse` fed from the returned sender. The response ends when every
+    // sender has been dropped:
+    pub fn channel(buffer: usize) -> (EventSender, Self) {
+        let (tx, rx) = mpsc::channel(buffer);
+        (EventSender { tx }, Self::new(Receiver { rx }))
+    }
+
+    // `None` turns keep-alive comments off:
+    pub fn with_keep_alive(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.keep_alive = interval;
+        self
+    }
+}
+
+impl From<Sse> for Response {
+    fn from(sse: Sse) -> Self {
+        let stream = Frames {
+            events: sse.events,
+            period: sse.keep_alive,
+            keep_alive: None,
+        };
+
+        let mut response = Response::new();
+        response
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            .with_body_stream(stream);
+
+        response
+    }
+}
+
+struct Receiver {
+    rx: mpsc::Receiver<Event>,
+}
+
+impl Stream for Receiver {
+    type Item = Event;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+// The encoded events, with keep-alive comments in between. The timer is
+// started on the first poll, so a response can be built outside of a
+// runtime:
+struct Frames {
+    events: Events,
+    period: Option<Duration>,
+    keep_alive: Option<Interval>,
+}
+
+impl Stream for Frames {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if let (Some(period), None) = (self.period, &self.keep_alive) {
+            let mut interval =
+                time::interval_at(time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            self.keep_alive = Some(interval);
+        }
+
+        if let Poll::Ready(event) = self.events.as_mut().poll_next(cx) {
+            if let Some(interval) = &mut self.keep_alive {
+                interval.reset();
+            }
+
+            let frame = event.map(|event| Ok(event.to_string().into_bytes()));
+            return Poll::Ready(frame);
+        }
+
+        match &mut self.keep_alive {
+            Some(interval) => match interval.poll_tick(cx) {
+                Poll::Ready(_) => Poll::Ready(Some(Ok(b":\n\n".to_vec()))),
+                Poll::Pending => Poll::Pending,
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, InvalidField, Sse};
+    use crate::{Response, Status};
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_event_format() {
+        let mut event = Event::new();
+        event
+            .with_event("price")
+            .unwrap()
+            .with_id("42")
+            .unwrap()
+            .with_retry(Duration::from_secs(3))
+            .with_data("line one\nline two");
+
+        assert_eq!(
+            event.to_string(),
+            "event: price\nid: 42\nretry: 3000\n\
+             data: line one\ndata: line two\n\n"
+        );
+
+        let mut event = Event::new();
+        event.with_comment("hello");
+        assert_eq!(event.to_string(), ": hello\n\n");
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let mut event = Event::new();
+        event
+            .with_comment("one\rtwo")
+            .with_data("a\r\nb\rc\nd\r\re");
+
+        assert_eq!(
+            event.to_string(),
+            ": one\n: two\n\
+             data: a\ndata: b\ndata: c\ndata: d\ndata: \ndata: e\n\n"
+        );
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let mut event = Event::new();
+
+        assert_eq!(
+            event.with_event("price\ndata: forged").err(),
+            Some(InvalidField("event"))
+        );
+        assert!(event.with_event("price\r").is_err());
+        assert!(event.with_id("4\r2").is_err());
+        assert!(event.with_id("4\0").is_err());
+        assert_eq!(event, Event::new());
+    }
+
+    // Nothing needs a runtime until the body is sent:
+    #[test]
+    fn test_response_without_runtime() {
+        let (_sender, sse) = Sse::channel(1);
+        let response: Response = sse.into();
+
+        assert_eq!(response.status(), &Status::OK);
+        assert_eq!(
+            response.header("Content-Type").unwrap(),
+            "text/event-stream"
+        );
+    }
+}
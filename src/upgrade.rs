@@ -0,0 +1,103 @@
+use crate::router::BoxFuture;
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+// The connection after a `101 Switching Protocols` response has been
+// sent. Bytes the client sent after the request head, which were read
+// along with it, come first:
+pub struct Upgraded {
+    stream: TcpStream,
+    buffered: Vec<u8>,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: TcpStream, buffered: Vec<u8>) -> Self {
+        Self { stream, buffered }
+    }
+
+    // The socket, and the bytes already read from it that haven't been
+    // consumed yet:
+    pub fn into_parts(self) -> (TcpStream, Vec<u8>) {
+        (self.stream, self.buffered)
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("buffered", &self.buffered.len())
+            .finish()
+    }
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.buffered.is_empty() {
+            return Pin::new(&mut self.stream).poll_read(cx, buf);
+        }
+
+        let n = self.buffered.len().min(buf.remaining());
+        buf.put_slice(&self.buffered[..n]);
+        self.buffered.drain(..n);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+type Callback = Box<dyn FnOnce(Upgraded) -> BoxFuture<()> + Send>;
+
+// What a response does with the connection once it has been sent:
+pub(crate) struct OnUpgrade(Callback);
+
+impl OnUpgrade {
+    pub(crate) fn new<F, Fut>(callback: F) -> Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Self(Box::new(move |upgraded| Box::pin(callback(upgraded))))
+    }
+
+    pub(crate) async fn run(self, upgraded: Upgraded) {
+        (self.0)(upgraded).await
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnUpgrade")
+    }
+}
@@ -0,0 +1,726 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::{Method, Request};
+use crate::response::{Response, Status};
+use crate::upgrade::Upgraded;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Buf, BufMut, BytesMut};
+use futures_core::Stream;
+use futures_sink::Sink;
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+// Appended to the client's key to prove the server speaks WebSocket:
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const DEFAULT_MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum WebSocketError {
+    Protocol(&'static str),
+    MessageTooLarge,
+    Io(io::Error),
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Protocol(reason) => {
+                write!(f, "protocol error: {}", reason)
+            }
+            WebSocketError::MessageTooLarge => f.write_str("message too large"),
+            WebSocketError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for WebSocketError {}
+
+impl WebSocketError {
+    // The code the connection is closed with after this error, if the
+    // client can still be told:
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(1002),
+            WebSocketError::MessageTooLarge => Some(1009),
+            WebSocketError::Io(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> Self {
+        WebSocketError::Io(e)
+    }
+}
+
+// A valid WebSocket handshake, ready to be accepted:
+#[derive(Debug)]
+pub struct WebSocketUpgrade {
+    key: String,
+    max_message: usize,
+}
+
+impl WebSocketUpgrade {
+    // Messages bigger than this close the connection:
+    pub fn with_max_message(&mut self, max: usize) -> &mut Self {
+        self.max_message = max;
+        self
+    }
+
+    // The `101 Switching Protocols` response. Once it has been sent,
+    // `callback` gets the socket:
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let codec = WebSocketCodec::new(self.max_message);
+
+        let mut response = Response::new();
+        response
+            .with_status(Status::SwitchingProtocols)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", &accept_key(&self.key))
+            .with_upgrade(move |upgraded| {
+                let socket = WebSocket {
+                    framed: Framed::new(upgraded, codec),
+                    close_sent: false,
+                    close_received: false,
+                };
+                callback(socket)
+            });
+
+        response
+    }
+}
+
+impl FromRequest for WebSocketUpgrade {
+    fn from_request(request: &Request) -> Result<Self, Rejection> {
+        let has_token = |name: &str, token: &str| {
+            request.header(name).is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|v| v.trim().eq_ignore_ascii_case(token))
+            })
+        };
+
+        if request.method() != &Method::GET
+            || !has_token("Upgrade", "websocket")
+            || !has_token("Connection", "upgrade")
+        {
+            Err(Rejection::new(
+                Status::BadRequest,
+                "Expected a WebSocket upgrade",
+            ))?
+        }
+
+        if request.header("Sec-WebSocket-Version").map(|v| v.trim())
+            != Some("13")
+        {
+            Err(Rejection::new(
+                Status::UpgradeRequired,
+                "Unsupported WebSocket version",
+            ))?
+        }
+
+        let key = match request.header("Sec-WebSocket-Key") {
+            Some(key) => key.trim(),
+            None => Err(Rejection::new(
+                Status::BadRequest,
+                "Missing Sec-WebSocket-Key",
+            ))?,
+        };
+
+        // The key is 16 random bytes, base64 encoded:
+        match STANDARD.decode(key) {
+            Ok(decoded) if decoded.len() == 16 => {}
+            _ => Err(Rejection::new(
+                Status::BadRequest,
+                "Invalid Sec-WebSocket-Key",
+            ))?,
+        }
+
+        Ok(Self {
+            key: key.to_owned(),
+            max_message: DEFAULT_MAX_MESSAGE,
+        })
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+
+    STANDARD.encode(sha1.finalize())
+}
+
+// An accepted WebSocket. Pings are answered and a close is echoed by
+// `recv`. It is also a Stream and Sink of messages, for use with
+// combinators, in which case that is left to the caller:
+pub struct WebSocket {
+    framed: Framed<Upgraded, WebSocketCodec>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    // The next message, `None` once the connection has closed or the
+    // client's close frame has been returned:
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        if self.close_received {
+            return None;
+        }
+
+        let message =
+            poll_fn(|cx| Pin::new(&mut self.framed).poll_next(cx)).await?;
+
+        let reply = match &message {
+            Ok(Message::Ping(data)) => Message::Pong(data.clone()),
+            Ok(Message::Close(_)) if self.close_sent => {
+                self.close_received = true;
+                return Some(message);
+            }
+            Ok(Message::Close(frame)) => {
+                self.close_received = true;
+                Message::Close(frame.clone())
+            }
+            // The connection has failed. The client is told why, and
+            // nothing more is read from it:
+            Err(e) => {
+                if let (Some(code), false) = (e.close_code(), self.close_sent) {
+                    self.close_received = true;
+                    let frame = CloseFrame {
+                        code,
+                        reason: String::new(),
+                    };
+                    let _ = self.send(Message::Close(Some(frame))).await;
+                }
+                return Some(message);
+            }
+            _ => return Some(message),
+        };
+
+        if let Err(e) = self.send(reply).await {
+            return Some(Err(e));
+        }
+
+        Some(message)
+    }
+
+    pub async fn send(
+        &mut self,
+        message: Message,
+    ) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            Err(WebSocketError::Protocol("send after close"))?
+        }
+
+        if let Message::Close(_) = message {
+            self.close_sent = true;
+        }
+
+        let mut framed = Pin::new(&mut self.framed);
+        poll_fn(|cx| framed.as_mut().poll_ready(cx)).await?;
+        framed.as_mut().start_send(message)?;
+        poll_fn(|cx| framed.as_mut().poll_flush(cx)).await
+    }
+
+    // Starts the closing handshake. Keep calling `recv` until it returns
+    // `None` to wait for the client's reply:
+    pub async fn close(
+        &mut self,
+        frame: Option<CloseFrame>,
+    ) -> Result<(), WebSocketError> {
+        self.send(Message::Close(frame)).await
+    }
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WebSocket")
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message, WebSocketError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.framed).poll_next(cx)
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = WebSocketError;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_ready(cx)
+    }
+
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        message: Message,
+    ) -> Result<(), Self::Error> {
+        if let Message::Close(_) = message {
+            self.close_sent = true;
+        }
+
+        Pin::new(&mut self.framed).start_send(message)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_flush(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.framed).poll_close(cx)
+    }
+}
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+// The server side of RFC 6455 framing. Client frames must be masked,
+// server frames are sent unmasked and unfragmented. Fragmented messages
+// are put back together, control frames may arrive in between:
+#[derive(Debug)]
+pub struct WebSocketCodec {
+    max_message: usize,
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+impl WebSocketCodec {
+    pub fn new(max_message: usize) -> Self {
+        Self {
+            max_message,
+            fragments: None,
+        }
+    }
+
+    fn message(
+        opcode: u8,
+        payload: Vec<u8>,
+    ) -> Result<Message, WebSocketError> {
+        match opcode {
+            OP_TEXT => match String::from_utf8(payload) {
+                Ok(text) => Ok(Message::Text(text)),
+                Err(_) => Err(WebSocketError::Protocol("invalid UTF-8 text")),
+            },
+            OP_BINARY => Ok(Message::Binary(payload)),
+            OP_PING => Ok(Message::Ping(payload)),
+            OP_PONG => Ok(Message::Pong(payload)),
+            _ => close_frame(&payload).map(Message::Close),
+        }
+    }
+}
+
+impl Decoder for WebSocketCodec {
+    type Item = Message;
+    type Error = WebSocketError;
+
+    fn decode(
+        &mut self,
+        src: &mut BytesMut,
+    ) -> Result<Option<Message>, WebSocketError> {
+        loop {
+            let header = match parse_header(src)? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            let buffered = self.fragments.as_ref().map_or(0, |(_, b)| b.len());
+            if buffered as u64 + header.length > self.max_message as u64 {
+                Err(WebSocketError::MessageTooLarge)?
+            }
+
+            let frame_len = header.header_len + header.length as usize;
+            if src.len() < frame_len {
+                src.reserve(frame_len - src.len());
+                return Ok(None);
+            }
+
+            src.advance(header.header_len);
+            let mut payload = src.split_to(header.length as usize).to_vec();
+            apply_mask(&mut payload, header.mask);
+
+            if header.opcode >= OP_CLOSE {
+                return Self::message(header.opcode, payload).map(Some);
+            }
+
+            let (opcode, mut data) =
+                match (header.opcode, self.fragments.take()) {
+                    (OP_CONTINUATION, Some(fragments)) => fragments,
+                    (OP_CONTINUATION, None) => Err(WebSocketError::Protocol(
+                        "unexpected continuation",
+                    ))?,
+                    (_, Some(_)) => Err(WebSocketError::Protocol(
+                        "expected a continuation",
+                    ))?,
+                    (opcode, None) => (opcode, Vec::new()),
+                };
+            data.extend(payload);
+
+            if header.fin {
+                return Self::message(opcode, data).map(Some);
+            }
+
+            self.fragments = Some((opcode, data));
+        }
+    }
+}
+
+impl Encoder<Message> for WebSocketCodec {
+    type Error = WebSocketError;
+
+    fn encode(
+        &mut self,
+        message: Message,
+        dst: &mut BytesMut,
+    ) -> Result<(), WebSocketError> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OP_TEXT, text.into_bytes()),
+            Message::Binary(data) => (OP_BINARY, data),
+            Message::Ping(data) => (OP_PING, data),
+            Message::Pong(data) => (OP_PONG, data),
+            Message::Close(None) => (OP_CLOSE, Vec::new()),
+            Message::Close(Some(frame)) => {
+                let mut payload = frame.code.to_be_bytes().to_vec();
+                payload.extend(frame.reason.into_bytes());
+                (OP_CLOSE, payload)
+            }
+        };
+
+        if opcode >= OP_CLOSE && payload.len() > 125 {
+            Err(WebSocketError::Protocol("control frame too large"))?
+        }
+
+        dst.reserve(payload.len() + 10);
+        dst.put_u8(0x80 | opcode);
+
+        match payload.len() {
+            len @ 0..=125 => dst.put_u8(len as u8),
+            len @ 126..=0xFFFF => {
+                dst.put_u8(126);
+                dst.put_u16(len as u16);
+            }
+            len => {
+                dst.put_u8(127);
+                dst.put_u64(len as u64);
+            }
+        }
+
+        dst.extend_from_slice(&payload);
+        Ok(())
+    }
+}
+
+struct Header {
+    fin: bool,
+    opcode: u8,
+    mask: [u8; 4],
+    length: u64,
+    header_len: usize,
+}
+
+// `None` until the whole header has arrived:
+fn parse_header(src: &[u8]) -> Result<Option<Header>, WebSocketError> {
+    if src.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin = src[0] & 0x80 != 0;
+    let opcode = src[0] & 0x0F;
+
+    if src[0] & 0x70 != 0 {
+        Err(WebSocketError::Protocol("reserved bits set"))?
+    }
+
+    if !matches!(
+        opcode,
+        OP_CONTINUATION | OP_TEXT | OP_BINARY | OP_CLOSE | OP_PING | OP_PONG
+    ) {
+        Err(WebSocketError::Protocol("unknown opcode"))?
+    }
+
+    if src[1] & 0x80 == 0 {
+        Err(WebSocketError::Protocol("client frames must be masked"))?
+    }
+
+    let (length, offset) = match src[1] & 0x7F {
+        126 if src.len() >= 4 => {
+            (u16::from_be_bytes([src[2], src[3]]) as u64, 4)
+        }
+        127 if src.len() >= 10 => {
+            let mut length = [0; 8];
+            length.copy_from_slice(&src[2..10]);
+            (u64::from_be_bytes(length), 10)
+        }
+        126 | 127 => return Ok(None),
+        length => (length as u64, 2),
+    };
+
+    if opcode >= OP_CLOSE && (!fin || length > 125) {
+        Err(WebSocketError::Protocol("invalid control frame"))?
+    }
+
+    if src.len() < offset + 4 {
+        return Ok(None);
+    }
+
+    let mut mask = [0; 4];
+    mask.copy_from_slice(&src[offset..offset + 4]);
+
+    Ok(Some(Header {
+        fin,
+        opcode,
+        mask,
+        length,
+        header_len: offset + 4,
+    }))
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+fn close_frame(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    let (code, reason) = match payload {
+        [] => return Ok(None),
+        [_] => Err(WebSocketError::Protocol("invalid close frame"))?,
+        [high, low, reason @ ..] => (u16::from_be_bytes([*high, *low]), reason),
+    };
+
+    if !is_close_code(code) {
+        Err(WebSocketError::Protocol("invalid close code"))?
+    }
+
+    match std::str::from_utf8(reason) {
+        Ok(reason) => Ok(Some(CloseFrame {
+            code,
+            reason: reason.to_owned(),
+        })),
+        Err(_) => Err(WebSocketError::Protocol("invalid close reason")),
+    }
+}
+
+// Codes a peer may send, from section 7.4 of RFC 6455. 1005, 1006 and
+// 1015 only stand in for a missing code locally, 1016-2999 are reserved
+// for the protocol and registered codes end at 1014:
+fn is_close_code(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        accept_key, apply_mask, CloseFrame, Message, WebSocketCodec,
+        WebSocketError, WebSocketUpgrade,
+    };
+    use crate::{Method, Request, Status};
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    // A frame as a client sends it, masked:
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![(fin as u8) << 7 | opcode];
+
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend((len as u16).to_be_bytes());
+            }
+        }
+
+        let mut payload = payload.to_vec();
+        apply_mask(&mut payload, mask);
+        frame.extend(mask);
+        frame.extend(payload);
+
+        frame
+    }
+
+    fn decode_all(
+        codec: &mut WebSocketCodec,
+        bytes: &[u8],
+    ) -> Result<Vec<Message>, WebSocketError> {
+        let mut src = BytesMut::from(bytes);
+        let mut messages = Vec::new();
+
+        while let Some(message) = codec.decode(&mut src)? {
+            messages.push(message);
+        }
+
+        Ok(messages)
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455:
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_handshake_validation() {
+        let mut request = Request::default();
+        request
+            .with_start_line(Method::GET, "/ws", "HTTP/1.1")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "keep-alive, Upgrade")
+            .with_header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .with_header("Sec-WebSocket-Version", "13");
+
+        let upgrade = request.extract::<WebSocketUpgrade>().unwrap();
+        let response = upgrade.on_upgrade(|_| async {});
+        assert_eq!(response.status(), &Status::SwitchingProtocols);
+        assert_eq!(
+            response.headers().get("Sec-WebSocket-Accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        request.with_header("Sec-WebSocket-Version", "8");
+        let rejection = request.extract::<WebSocketUpgrade>().unwrap_err();
+        assert_eq!(rejection.status(), &Status::UpgradeRequired);
+
+        let mut request = Request::default();
+        request.with_start_line(Method::GET, "/ws", "HTTP/1.1");
+        let rejection = request.extract::<WebSocketUpgrade>().unwrap_err();
+        assert_eq!(rejection.status(), &Status::BadRequest);
+    }
+
+    #[test]
+    fn test_fragmented_message() {
+        let mut bytes = client_frame(false, 0x1, b"Hel");
+        bytes.extend(client_frame(true, 0x9, b"ping"));
+        bytes.extend(client_frame(true, 0x0, b"lo"));
+        bytes.extend(client_frame(true, 0x8, b"\x03\xe8bye"));
+
+        let messages = decode_all(&mut WebSocketCodec::new(1024), &bytes);
+        assert_eq!(
+            messages.unwrap(),
+            vec![
+                Message::Ping(b"ping".to_vec()),
+                Message::Text("Hello".to_owned()),
+                Message::Close(Some(CloseFrame {
+                    code: 1000,
+                    reason: "bye".to_owned()
+                })),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_frames() {
+        let bytes = client_frame(true, 0x2, &[7; 300]);
+        let mut codec = WebSocketCodec::new(1024);
+
+        let mut src = BytesMut::from(&bytes[..3]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&bytes[3..]);
+        let message = codec.decode(&mut src).unwrap();
+        assert_eq!(message, Some(Message::Binary(vec![7; 300])));
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        let mut codec = WebSocketCodec::new(1024);
+
+        // Unmasked:
+        let result = decode_all(&mut codec, b"\x81\x02hi");
+        assert!(matches!(result, Err(WebSocketError::Protocol(_))));
+
+        let result = decode_all(&mut codec, &client_frame(true, 0x1, b"\xff"));
+        assert!(matches!(result, Err(WebSocketError::Protocol(_))));
+
+        let bytes = client_frame(true, 0x2, &[0; 2000]);
+        let result = decode_all(&mut codec, &bytes);
+        assert!(matches!(result, Err(WebSocketError::MessageTooLarge)));
+    }
+
+    #[test]
+    fn test_close_codes() {
+        let close = |code: u16| {
+            let mut payload = code.to_be_bytes().to_vec();
+            payload.extend(b"bye");
+            let bytes = client_frame(true, 0x8, &payload);
+            decode_all(&mut WebSocketCodec::new(1024), &bytes)
+        };
+
+        for code in [1000, 1001, 1003, 1007, 1011, 3000, 4999] {
+            let frame = CloseFrame {
+                code,
+                reason: "bye".to_owned(),
+            };
+            assert_eq!(close(code).unwrap(), vec![Message::Close(Some(frame))]);
+        }
+
+        for code in [0, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            let result = close(code);
+            assert!(matches!(result, Err(WebSocketError::Protocol(_))));
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut codec = WebSocketCodec::new(1024);
+        let mut dst = BytesMut::new();
+
+        codec
+            .encode(Message::Text("hi".to_owned()), &mut dst)
+            .unwrap();
+        codec
+            .encode(Message::Binary(vec![1; 200]), &mut dst)
+            .unwrap();
+
+        assert_eq!(&dst[..4], b"\x81\x02hi");
+        assert_eq!(&dst[4..8], &[0x82, 126, 0, 200]);
+        assert_eq!(dst.len(), 8 + 200);
+    }
+}
@@ -1,28 +1,24 @@
 use chrono::prelude::*;
+use serde::Deserialize;
 
 use potato::app::App;
+use potato::cookie_jar::Key;
 use potato::request::{Method, Request};
 use potato::response::{Cookie, Response, Status};
 use potato::router::Router;
 
 fn get(request: Request) -> Response {
+    let mut secure = Cookie::new("secure", "and http only").unwrap();
+    secure.with_secure(true).with_http_only(true);
+
+    let mut expiring = Cookie::new("notsecure", "with expiry").unwrap();
+    expiring.with_expires(Utc.with_ymd_and_hms(2022, 12, 1, 12, 0, 0).unwrap());
+
     let mut response = Response::new();
     response
         .with_header("Content-Type", "text/html")
-        .with_cookie(Cookie {
-            key: "secure",
-            value: "and http only",
-            expires: None,
-            secure: true,
-            http_only: true,
-        })
-        .with_cookie(Cookie {
-            key: "notsecure",
-            value: "with expiry",
-            expires: Some(chrono::Utc.ymd(2022, 12, 1).and_hms(12, 00, 00)),
-            secure: false,
-            http_only: false,
-        })
+        .with_cookie(&secure)
+        .with_cookie(&expiring)
         .with_content(format!(
             "You sent: {:?}, {} and {}",
             request.method(),
@@ -47,20 +43,79 @@ fn post(request: Request) -> Response {
     response
 }
 
+#[derive(Deserialize)]
+struct DeleteParams {
+    id: u32,
+}
+
 fn delete(request: Request) -> Response {
+    let params: DeleteParams = match request.query_as() {
+        Ok(params) => params,
+        Err(rejection) => return rejection.into(),
+    };
+
     let mut response = Response::new();
+    response.with_header("id", &params.id.to_string());
 
-    let id = match request.query().get("id") {
-        Some(id) => id,
-        None => {
-            response
-                .with_status(Status::BadRequest)
-                .with_content("You need to give an ID!".to_owned());
-            return response;
-        }
+    response
+}
+
+fn preferences(request: Request) -> Response {
+    let cookies = request.cookies();
+
+    let theme = match cookies.get("darkmode").map(|v| v.as_str()) {
+        Some("true") => "dark",
+        _ => "light",
     };
 
-    response.with_header("id", id);
+    let mut response = Response::new();
+    response.with_content(theme.to_owned());
+
+    response
+}
+
+fn login(request: Request) -> Response {
+    let mut jar = request.cookie_jar();
+    if let Err(e) = jar.add_signed(Cookie::new("user", "bob").unwrap()) {
+        let mut response = Response::new();
+        response
+            .with_status(Status::Internal)
+            .with_content(e.to_string());
+        return response;
+    }
+
+    let mut response = Response::new();
+    response.with_cookie_jar(&jar);
+
+    response
+}
+
+fn whoami(request: Request) -> Response {
+    let user = request.cookie_jar().get_signed("user");
+
+    let mut response = Response::new();
+    response.with_content(user.unwrap_or_else(|| "nobody".to_owned()));
+
+    response
+}
+
+fn greeting(request: Request) -> Response {
+    let media_type = match request.negotiate(&["application/json", "text/html"])
+    {
+        Ok(media_type) => media_type,
+        Err(rejection) => return rejection.into(),
+    };
+
+    let content = match media_type {
+        "text/html" => "<p>Hello</p>",
+        _ => r#"{"greeting":"Hello"}"#,
+    };
+
+    let mut response = Response::new();
+    response
+        .with_header("Content-Type", media_type)
+        .with_header("Vary", "Accept")
+        .with_content(content.to_owned());
 
     response
 }
@@ -72,9 +127,16 @@ async fn init() -> App {
         .add(Method::GET, "/potato", get)
         .add(Method::POST, "/potato", post)
         .add(Method::PATCH, "/potato", get)
-        .add(Method::DELETE, "/potato", delete);
+        .add(Method::DELETE, "/potato", delete)
+        .add(Method::GET, "/preferences", preferences)
+        .add(Method::POST, "/login", login)
+        .add(Method::GET, "/whoami", whoami)
+        .add(Method::GET, "/greeting", greeting);
+
+    let mut app = App::new(router);
+    app.with_cookie_key(Key::try_from_secret(&[7; 32]).unwrap());
 
-    App::new(router)
+    app
 }
 
 #[tokio::test]
@@ -90,8 +152,8 @@ async fn test_get() {
     assert_eq!(response.to_string(), "HTTP/1.1 200 OK\r\n\
 Content-Length: 35\r\n\
 Content-Type: text/html\r\n\
-Set-Cookie: secure=and http only; Secure; HttpOnly\r\n\
-Set-Cookie: notsecure=with expiry; Expires=Thu, 01 Dec 2022 12:00:00 +0000\r\n\r\n\
+Set-Cookie: secure=and%20http%20only; Secure; HttpOnly\r\n\
+Set-Cookie: notsecure=with%20expiry; Expires=Thu, 01 Dec 2022 12:00:00 GMT\r\n\r\n\
 You sent: GET, /potato and HTTP/1.1".to_owned());
 }
 
@@ -127,3 +189,110 @@ async fn test_delete() {
 
     assert_eq!(response.headers().get("id").unwrap(), "1234");
 }
+
+#[tokio::test]
+async fn test_delete_with_invalid_id() {
+    let mut app = init().await;
+
+    let mut request = Request::default();
+    request.with_start_line(Method::DELETE, "/potato?id=abc", "HTTP/1.1");
+
+    let response = app.request(request).await.unwrap();
+
+    assert_eq!(response.status(), &Status::BadRequest);
+    assert_eq!(
+        response.content(),
+        "Invalid query string: invalid value `abc` for `id`: \
+         expected an unsigned integer"
+    );
+}
+
+#[tokio::test]
+async fn test_delete_without_id() {
+    let mut app = init().await;
+
+    let mut request = Request::default();
+    request.with_start_line(Method::DELETE, "/potato", "HTTP/1.1");
+
+    let response = app.request(request).await.unwrap();
+
+    assert_eq!(response.status(), &Status::BadRequest);
+    assert_eq!(
+        response.content(),
+        "Invalid query string: missing field `id`"
+    );
+}
+
+#[tokio::test]
+async fn test_cookies() {
+    let mut app = init().await;
+
+    let mut request = Request::default();
+    request
+        .with_start_line(Method::GET, "/preferences", "HTTP/1.1")
+        .with_header("Cookie", "session=abc; darkmode=true");
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), "dark");
+
+    let mut request = Request::default();
+    request.with_start_line(Method::GET, "/preferences", "HTTP/1.1");
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), "light");
+}
+
+#[tokio::test]
+async fn test_signed_cookies() {
+    let mut app = init().await;
+
+    let mut request = Request::default();
+    request.with_start_line(Method::POST, "/login", "HTTP/1.1");
+
+    let response = app.request(request).await.unwrap();
+    let cookie = response.cookies()[0].clone();
+    assert!(cookie.starts_with("user=") && cookie.ends_with("bob"));
+
+    let mut request = Request::default();
+    request
+        .with_start_line(Method::GET, "/whoami", "HTTP/1.1")
+        .with_header("Cookie", &cookie);
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), "bob");
+
+    let mut request = Request::default();
+    request
+        .with_start_line(Method::GET, "/whoami", "HTTP/1.1")
+        .with_header("Cookie", &cookie.replace("bob", "eve"));
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), "nobody");
+}
+
+#[tokio::test]
+async fn test_negotiate() {
+    let mut app = init().await;
+
+    let mut request = Request::default();
+    request
+        .with_start_line(Method::GET, "/greeting", "HTTP/1.1")
+        .with_header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8");
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), "<p>Hello</p>");
+
+    let mut request = Request::default();
+    request.with_start_line(Method::GET, "/greeting", "HTTP/1.1");
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.content(), r#"{"greeting":"Hello"}"#);
+
+    let mut request = Request::default();
+    request
+        .with_start_line(Method::GET, "/greeting", "HTTP/1.1")
+        .with_header("Accept", "image/png");
+
+    let response = app.request(request).await.unwrap();
+    assert_eq!(response.status(), &Status::NotAcceptable);
+}